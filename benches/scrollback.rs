@@ -0,0 +1,72 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Compares building the whole log against building only the rows in view,
+//! with 100k messages in the scrollback. Run it with `cargo bench`.
+
+// The binary has no library to link against, so pull the module in directly.
+#[allow(dead_code)]
+#[path = "../src/scrollback.rs"]
+mod scrollback;
+
+use iced::widget::{text, Column};
+use scrollback::Scrollback;
+use std::time::{Duration, Instant};
+
+const MESSAGES: usize = 100_000;
+const ROUNDS: u32 = 20;
+/// About what fits in a window at the default size and text size.
+const VIEW: f32 = 40.0;
+
+fn main() {
+    let contents: Vec<String> = (0..MESSAGES)
+        .map(|i| format!("message number {i} with a bit of text in it"))
+        .collect();
+    let mut log = Scrollback::new(0);
+    for content in &contents {
+        log.push(content.clone(), 1);
+    }
+
+    let everything = time(|| {
+        let column: Column<'_, ()> =
+            Column::with_children(contents.iter().map(|c| text(c).into()).collect());
+        drop(column);
+    });
+    let visible = time(|| {
+        let first = (log.height() as f32 - VIEW) / 2.0;
+        let column: Column<'_, ()> = Column::with_children(
+            log.visible(first, VIEW)
+                .filter_map(|i| log.get(i))
+                .map(|c| text(c).into())
+                .collect(),
+        );
+        drop(column);
+    });
+    println!("whole log:     {everything:?} per redraw");
+    println!("visible rows:  {visible:?} per redraw");
+    println!(
+        "speedup:       {:.0}x",
+        everything.as_secs_f64() / visible.as_secs_f64()
+    );
+}
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS
+}
@@ -0,0 +1,444 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Slash commands typed into the message box. The client's own commands are
+//! parsed into a [`Command`] with their arguments checked up front, so a typo
+//! gets a usage message instead of being sent to the chat.
+//!
+//! Scripts can add commands by defining `commands`, a list of
+//! `(name usage help)` lists, and a `command-<name>` function for each one.
+//! The function gets everything after the name as a string, and if it returns
+//! a string that's sent as a message.
+
+use crate::{export, theme};
+use ketos::{FromValueRef, Interpreter, Value};
+use std::time::{Duration, Instant};
+
+/// What a slash command asks the client to do.
+#[derive(Clone, Debug)]
+pub enum Command {
+    Clear,
+    Color(String),
+    Export(export::Format, export::Range),
+    Help(Option<String>),
+    /// Lists who's ignored when there's no name, and mutes them for a while
+    /// when there are seconds.
+    Ignore {
+        name: Option<String>,
+        seconds: Option<u64>,
+    },
+    Me(String),
+    Nick(String),
+    Quit,
+    Reconnect,
+    Reply {
+        id: usize,
+        text: String,
+    },
+    Script {
+        args: String,
+        name: String,
+        /// Which of the loaded scripts defined the command.
+        script: usize,
+    },
+    Unignore(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Spec {
+    pub help: String,
+    /// The name without the leading slash.
+    pub name: String,
+    pub usage: String,
+}
+
+impl Spec {
+    fn new(name: &str, usage: &str, help: &str) -> Self {
+        Self {
+            help: help.to_string(),
+            name: name.to_string(),
+            usage: usage.to_string(),
+        }
+    }
+
+    /// Writes the command as it would be typed, with its arguments.
+    pub fn synopsis(&self) -> String {
+        if self.usage.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.usage)
+        }
+    }
+}
+
+fn builtin() -> Vec<Spec> {
+    vec![
+        Spec::new("clear", "", "Empties the log"),
+        Spec::new("color", "#RRGGBB", "Changes the color of your name"),
+        Spec::new(
+            "export",
+            "[text|html|json] [from] [to]",
+            "Saves the log, or the part of it between two times, to a file",
+        ),
+        Spec::new("help", "[command]", "Lists the commands, or explains one"),
+        Spec::new(
+            "ignore",
+            "[name] [minutes]",
+            "Ignores someone, for a while if minutes are given, or lists who's ignored",
+        ),
+        Spec::new("me", "text", "Describes what you're doing"),
+        Spec::new("nick", "name", "Changes your name"),
+        Spec::new("quit", "", "Closes the client"),
+        Spec::new("reconnect", "", "Connects to the server again"),
+        Spec::new("reply", "id text", "Replies to the message with that ID"),
+        Spec::new("unignore", "name", "Stops ignoring someone"),
+    ]
+}
+
+/// Every command the client knows about, its own and the ones scripts added.
+pub struct Registry {
+    builtin: Vec<Spec>,
+    /// Commands from scripts, with the index of the script they came from.
+    scripted: Vec<(Spec, usize)>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self {
+            builtin: builtin(),
+            scripted: Vec::new(),
+        }
+    }
+}
+
+impl Registry {
+    /// Collects the commands the scripts define. Anything malformed or
+    /// clashing with another command is left out and reported.
+    pub fn new(scripts: &[Interpreter]) -> (Self, Vec<String>) {
+        let mut registry = Self::default();
+        let mut errors = Vec::new();
+        for (index, script) in scripts.iter().enumerate() {
+            let Some(value) = script.get_value("commands") else {
+                continue;
+            };
+            let specs = match script_specs(&value) {
+                Ok(specs) => specs,
+                Err(e) => {
+                    errors.push(format!("Script {}: {e}", index + 1));
+                    continue;
+                }
+            };
+            for spec in specs {
+                if registry.get(&spec.name).is_some() {
+                    errors.push(format!(
+                        "Script {}: /{} is already a command",
+                        index + 1,
+                        spec.name
+                    ));
+                } else {
+                    registry.scripted.push((spec, index));
+                }
+            }
+        }
+        (registry, errors)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Spec> {
+        self.iter().find(|spec| spec.name == name)
+    }
+
+    /// Lists every command, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &Spec> {
+        let mut specs: Vec<&Spec> = self
+            .builtin
+            .iter()
+            .chain(self.scripted.iter().map(|(spec, _)| spec))
+            .collect();
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        specs.into_iter()
+    }
+
+    /// Lists the commands as they're typed, for completion.
+    pub fn names(&self) -> Vec<String> {
+        self.iter().map(|spec| format!("/{}", spec.name)).collect()
+    }
+
+    /// Works out which command `input` is. `Ok(None)` means it's a message,
+    /// which includes anything starting with `//`. Send it through
+    /// [`unescape`].
+    pub fn parse(&self, input: &str) -> Result<Option<Command>, String> {
+        let Some(rest) = input.strip_prefix('/') else {
+            return Ok(None);
+        };
+        if rest.starts_with('/') {
+            return Ok(None);
+        }
+        let (name, args) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .map_or((rest.trim(), ""), |(name, args)| (name, args.trim()));
+        let Some(spec) = self.get(name) else {
+            return Err(format!(
+                "/{name} isn't a command, /help lists the ones there are"
+            ));
+        };
+        let usage = || format!("Usage: {}", spec.synopsis());
+        let mut words = args.split_whitespace();
+        let command = match name {
+            "clear" | "quit" | "reconnect" if !args.is_empty() => return Err(usage()),
+            "clear" => Command::Clear,
+            "quit" => Command::Quit,
+            "reconnect" => Command::Reconnect,
+            "color" => match (words.next(), words.next()) {
+                (Some(color), None) => match theme::parse_color(color) {
+                    Some(color) => Command::Color(theme::to_hex(color)),
+                    None => return Err(format!("\"{color}\" is not a color. {}", usage())),
+                },
+                _ => return Err(usage()),
+            },
+            "export" => {
+                if words.clone().count() > 3 {
+                    return Err(usage());
+                }
+                let format = words
+                    .next()
+                    .map_or(Ok(export::Format::Text), str::parse)
+                    .map_err(|e| format!("{e}. {}", usage()))?;
+                let from = words.next().unwrap_or_default();
+                let to = words.next().unwrap_or_default();
+                let range =
+                    export::Range::parse(from, to).map_err(|e| format!("{e}. {}", usage()))?;
+                Command::Export(format, range)
+            }
+            "help" => match (words.next(), words.next()) {
+                (topic, None) => {
+                    Command::Help(topic.map(|topic| topic.trim_start_matches('/').to_string()))
+                }
+                _ => return Err(usage()),
+            },
+            "ignore" => {
+                let name = words.next().map(ToString::to_string);
+                let seconds = match words.next().map(str::parse::<u64>) {
+                    Some(Ok(minutes)) => {
+                        let seconds = minutes
+                            .checked_mul(60)
+                            .filter(|&seconds| {
+                                Instant::now()
+                                    .checked_add(Duration::from_secs(seconds))
+                                    .is_some()
+                            })
+                            .ok_or_else(|| format!("{minutes} minutes is too long. {}", usage()))?;
+                        Some(seconds)
+                    }
+                    Some(Err(_)) => return Err(format!("Minutes must be a number. {}", usage())),
+                    None => None,
+                };
+                if words.next().is_some() {
+                    return Err(usage());
+                }
+                Command::Ignore { name, seconds }
+            }
+            "me" if args.is_empty() => return Err(usage()),
+            "me" => Command::Me(args.to_string()),
+            "nick" => match (words.next(), words.next()) {
+                (Some(name), None) => Command::Nick(name.to_string()),
+                _ => return Err(usage()),
+            },
+            "reply" => {
+                let id = match words.next().map(str::parse::<usize>) {
+                    Some(Ok(id)) => id,
+                    Some(Err(_)) => {
+                        return Err(format!("The message ID must be a number. {}", usage()))
+                    }
+                    None => return Err(usage()),
+                };
+                let text = args
+                    .split_once(char::is_whitespace)
+                    .map(|(_, text)| text.trim())
+                    .unwrap_or_default();
+                if text.is_empty() {
+                    return Err(usage());
+                }
+                Command::Reply {
+                    id,
+                    text: text.to_string(),
+                }
+            }
+            "unignore" => match (words.next(), words.next()) {
+                (Some(name), None) => Command::Unignore(name.to_string()),
+                _ => return Err(usage()),
+            },
+            _ => {
+                let script = self
+                    .scripted
+                    .iter()
+                    .find(|(spec, _)| spec.name == name)
+                    .map(|(_, script)| *script)
+                    .unwrap();
+                Command::Script {
+                    args: args.to_string(),
+                    name: name.to_string(),
+                    script,
+                }
+            }
+        };
+        Ok(Some(command))
+    }
+
+    /// Describes one command, or lists them all.
+    pub fn help(&self, topic: Option<&str>) -> Result<Vec<String>, String> {
+        match topic {
+            Some(name) => self
+                .get(name)
+                .map(|spec| vec![format!("{}: {}", spec.synopsis(), spec.help)])
+                .ok_or_else(|| format!("/{name} isn't a command")),
+            None => Ok(std::iter::once(String::from(
+                "Commands (start a message with // to send one starting with /):",
+            ))
+            .chain(
+                self.iter()
+                    .map(|spec| format!("{}: {}", spec.synopsis(), spec.help)),
+            )
+            .collect()),
+        }
+    }
+}
+
+/// Drops the first slash of a message starting with `//`, which is how a
+/// message that starts with `/` gets past the commands.
+pub fn unescape(message: &str) -> &str {
+    if message.starts_with("//") {
+        &message[1..]
+    } else {
+        message
+    }
+}
+
+/// Runs a command a script defined, returning the message to send if there is
+/// one.
+pub fn run_script(script: &Interpreter, name: &str, args: &str) -> Result<Option<String>, String> {
+    let function = format!("command-{name}");
+    match script.call(&function, vec![Value::String(args.into())]) {
+        Ok(Value::Unit) => Ok(None),
+        Ok(Value::String(text)) => Ok(Some(text.to_string())),
+        Ok(value) => Err(format!(
+            "{function} returned a {}, not a string",
+            value.type_name()
+        )),
+        Err(e) => Err(script.format_error(&e)),
+    }
+}
+
+/// Reads the `commands` list a script defined.
+fn script_specs(value: &Value) -> Result<Vec<Spec>, String> {
+    let malformed = || String::from("commands must be a list of (name usage help) lists");
+    let entries = match value {
+        Value::Unit => return Ok(Vec::new()),
+        value => <&[Value]>::from_value_ref(value).map_err(|_| malformed())?,
+    };
+    entries
+        .iter()
+        .map(|entry| {
+            let fields = <&[Value]>::from_value_ref(entry).map_err(|_| malformed())?;
+            let field = |i: usize| {
+                fields
+                    .get(i)
+                    .and_then(|field| <&str>::from_value_ref(field).ok())
+                    .ok_or_else(malformed)
+            };
+            let name = field(0)?.trim_start_matches('/');
+            if fields.len() != 3 || name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(malformed());
+            }
+            Ok(Spec::new(name, field(1)?, field(2)?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Result<Option<Command>, String> {
+        Registry::default().parse(input)
+    }
+
+    #[test]
+    fn messages_are_not_commands() {
+        assert!(matches!(parse("hello"), Ok(None)));
+        assert!(matches!(parse("//foo"), Ok(None)));
+    }
+
+    #[test]
+    fn unescape_drops_one_slash() {
+        assert_eq!(unescape("//foo"), "/foo");
+        assert_eq!(unescape("///foo"), "//foo");
+        assert_eq!(unescape("/foo"), "/foo");
+        assert_eq!(unescape("foo"), "foo");
+    }
+
+    #[test]
+    fn unknown_commands_are_errors() {
+        let error = parse("/frobnicate now").unwrap_err();
+        assert!(error.starts_with("/frobnicate isn't a command"), "{error}");
+    }
+
+    #[test]
+    fn bad_arguments_get_the_usage() {
+        for input in ["/clear now", "/nick", "/nick two names", "/color", "/me"] {
+            let error = parse(input).unwrap_err();
+            assert!(error.contains("Usage: /"), "{input}: {error}");
+        }
+        let error = parse("/color notacolor").unwrap_err();
+        assert!(error.contains("Usage: /color #RRGGBB"), "{error}");
+    }
+
+    #[test]
+    fn reply_needs_an_id_and_text() {
+        assert!(matches!(
+            parse("/reply 42 sounds good"),
+            Ok(Some(Command::Reply { id: 42, text })) if text == "sounds good"
+        ));
+        assert_eq!(parse("/reply 42").unwrap_err(), "Usage: /reply id text");
+        assert!(parse("/reply").is_err());
+        assert!(parse("/reply bob hi")
+            .unwrap_err()
+            .starts_with("The message ID must be a number"));
+    }
+
+    #[test]
+    fn ignore_takes_minutes() {
+        assert!(matches!(
+            parse("/ignore bob 5"),
+            Ok(Some(Command::Ignore { name: Some(name), seconds: Some(300) })) if name == "bob"
+        ));
+        assert!(matches!(
+            parse("/ignore"),
+            Ok(Some(Command::Ignore {
+                name: None,
+                seconds: None
+            }))
+        ));
+    }
+
+    #[test]
+    fn ignore_rejects_minutes_that_overflow() {
+        for minutes in ["999999999999999999", &u64::MAX.to_string()] {
+            let error = parse(&format!("/ignore bob {minutes}")).unwrap_err();
+            assert!(error.contains("minutes is too long"), "{error}");
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Completing the word at the end of the message box: names after `@` or on
+//! Tab, commands after a leading `/` and emote shortcodes after `:`.
+
+/// The most candidates offered at once.
+const LIMIT: usize = 8;
+
+pub struct Completion {
+    /// What the word can be replaced with, best first.
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    /// Where the word being completed starts in the input.
+    start: usize,
+}
+
+impl Completion {
+    /// Finds candidates for the last word of `input`. Names are only offered
+    /// for a bare word when `explicit`, meaning Tab was pressed. `names` is
+    /// only called when names are wanted, and should return the most recently
+    /// active first.
+    pub fn new<F: FnOnce() -> Vec<String>>(
+        input: &str,
+        names: F,
+        commands: &[String],
+        emotes: &[String],
+        explicit: bool,
+    ) -> Option<Self> {
+        let start = input
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + input[i..].chars().next().unwrap().len_utf8());
+        let word = &input[start..];
+        let candidates = if word.starts_with('/') && start == 0 {
+            matching(commands.iter().cloned(), word)
+        } else if let Some(name) = word.strip_prefix('@') {
+            matching(names(), name)
+                .into_iter()
+                .map(|name| format!("@{name}"))
+                .collect()
+        } else if word.starts_with(':') && word.len() > 1 {
+            matching(emotes.iter().cloned(), word)
+        } else if explicit && !word.is_empty() {
+            matching(names(), word)
+        } else {
+            Vec::new()
+        };
+        // There's nothing to offer if the word is already complete.
+        if candidates.is_empty() || candidates == [word] {
+            return None;
+        }
+        Some(Self {
+            candidates,
+            selected: 0,
+            start,
+        })
+    }
+
+    /// Replaces the word in `input` with the selected candidate.
+    pub fn apply(&self, input: &mut String) {
+        input.truncate(self.start);
+        input.push_str(&self.candidates[self.selected]);
+        input.push(' ');
+    }
+
+    pub fn next(&mut self) {
+        self.selected = (self.selected + 1) % self.candidates.len();
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = self
+            .selected
+            .checked_sub(1)
+            .unwrap_or(self.candidates.len() - 1);
+    }
+}
+
+/// Keeps the options that start with `prefix`, ignoring case.
+fn matching<I: IntoIterator<Item = String>>(options: I, prefix: &str) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    options
+        .into_iter()
+        .filter(|option| option.to_lowercase().starts_with(&prefix))
+        .take(LIMIT)
+        .collect()
+}
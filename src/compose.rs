@@ -0,0 +1,178 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! The message box. It remembers what was sent so it can be recalled with the
+//! arrow keys, and keeps what hasn't been sent yet per server on disk.
+
+use std::{collections::BTreeMap, fs, io};
+
+const DRAFTS_PATH: &str = "drafts.json";
+
+/// How many sent messages can be recalled.
+const RECALL_LIMIT: usize = 100;
+
+#[derive(Default)]
+pub struct Composer {
+    /// What's being typed on the current line.
+    pub input: String,
+    /// Lines finished with Shift+Enter, waiting to go out with the current
+    /// one.
+    pub lines: Vec<String>,
+    /// Which sent message is shown, counting back from the newest.
+    recall: Option<usize>,
+    sent: Vec<String>,
+    /// What was typed before recalling started, to come back to at the end.
+    stash: String,
+}
+
+impl Composer {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.input.is_empty()
+    }
+
+    /// Finishes the current line and starts a new one.
+    pub fn newline(&mut self) {
+        self.lines.push(std::mem::take(&mut self.input));
+        self.recall = None;
+    }
+
+    /// Shows the next newer sent message, or what was being typed once we're
+    /// past the newest.
+    pub fn newer(&mut self) {
+        match self.recall {
+            Some(0) => {
+                self.recall = None;
+                let stash = std::mem::take(&mut self.stash);
+                self.set_text(&stash);
+            }
+            Some(i) => {
+                self.recall = Some(i - 1);
+                self.show_recalled();
+            }
+            None => {}
+        }
+    }
+
+    /// Shows the next older sent message.
+    pub fn older(&mut self) {
+        let next = match self.recall {
+            Some(i) if i + 1 < self.sent.len() => i + 1,
+            Some(_) => return,
+            None if self.sent.is_empty() => return,
+            None => {
+                self.stash = self.text();
+                0
+            }
+        };
+        self.recall = Some(next);
+        self.show_recalled();
+    }
+
+    /// Replaces the current line with what was typed. Editing a recalled
+    /// message turns it into a new one.
+    pub fn set_input(&mut self, input: String) {
+        self.input = input;
+        self.recall = None;
+    }
+
+    /// Replaces everything in the box, splitting `text` into lines.
+    pub fn set_text(&mut self, text: &str) {
+        let mut lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        self.input = lines.pop().unwrap_or_default();
+        self.lines = lines;
+    }
+
+    /// Empties the box and returns what was in it, remembering it for recall.
+    pub fn take(&mut self) -> String {
+        let text = self.text();
+        self.lines.clear();
+        self.input.clear();
+        self.recall = None;
+        self.stash.clear();
+        if self.sent.last() != Some(&text) {
+            if self.sent.len() == RECALL_LIMIT {
+                self.sent.remove(0);
+            }
+            self.sent.push(text.clone());
+        }
+        text
+    }
+
+    /// Returns everything in the box, with the lines joined by line breaks.
+    pub fn text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if !self.lines.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&self.input);
+        text
+    }
+
+    fn show_recalled(&mut self) {
+        if let Some(text) = self.recall.and_then(|i| self.sent.iter().rev().nth(i)) {
+            let text = text.clone();
+            self.set_text(&text);
+        }
+    }
+}
+
+/// Unsent messages keyed on server, so they survive reconnects and
+/// restarts.
+#[derive(Default)]
+pub struct Drafts {
+    by_server: BTreeMap<String, String>,
+    /// Whether anything changed since the file was last written.
+    dirty: bool,
+}
+
+impl Drafts {
+    pub fn load() -> io::Result<Self> {
+        let by_server = match fs::read(DRAFTS_PATH) {
+            Ok(raw) => serde_json::from_slice(&raw)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            by_server,
+            dirty: false,
+        })
+    }
+
+    pub fn get(&self, server: &str) -> &str {
+        self.by_server.get(server).map_or("", String::as_str)
+    }
+
+    /// Writes the drafts out if they changed.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+        fs::write(DRAFTS_PATH, serde_json::to_vec(&self.by_server)?)
+    }
+
+    pub fn set(&mut self, server: &str, draft: String) {
+        if self.get(server) == draft {
+            return;
+        }
+        if draft.is_empty() {
+            self.by_server.remove(server);
+        } else {
+            self.by_server.insert(server.to_string(), draft);
+        }
+        self.dirty = true;
+    }
+}
@@ -15,32 +15,140 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>. */
 
 use serde::{Deserialize, Serialize};
-use std::{fs, path::Path};
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use toml_edit::{Document, Item};
 
-#[derive(Deserialize, Serialize)]
+/// The schema version written by this build. Bump it whenever a migration step
+/// is added to `migrate`.
+pub const VERSION: i64 = 1;
+
+/// Keys that are left out of the file entirely when they aren't set.
+const OPTIONAL_KEYS: &[&str] = &[
+    "colors",
+    "ignored",
+    "token",
+    "token_command",
+    "token_file",
+    "token_store",
+];
+
+// Every field falls back to its default so that files written by older builds
+// keep loading after new settings are added.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
 pub struct Configuration {
+    version: i64,
+    pub deleted: DeletedMode,
+    /// How long donations stay pinned above the log, in seconds. Zero turns
+    /// pinning off.
+    pub donation_pin: u64,
+    /// Emote shortcodes to offer when completing a word that starts with
+    /// `:`, like `:mattkc:`.
+    pub emotes: Vec<String>,
+    /// How close together, in seconds, messages from the same person have to
+    /// be to share a header. Zero never groups them.
+    pub group_window: u64,
+    /// Regexes that count as mentioning us, used as they are.
+    pub highlight_patterns: Vec<String>,
+    /// Words that count as mentioning us, besides our name. They match whole
+    /// words regardless of case.
+    pub highlights: Vec<String>,
+    /// Whether to keep everything the server sends in `logs/`.
+    pub history: bool,
+    /// How many messages to load from the history file at a time.
+    pub history_lines: usize,
+    pub ignored_mode: IgnoredMode,
+    pub layout: Layout,
+    /// How many characters the server takes in a message. The counter
+    /// warns when a message gets close. Zero turns the warning off.
+    pub message_limit: usize,
+    /// How long muting someone from the log lasts, in seconds.
+    pub mute_duration: u64,
+    pub presence: PresenceMode,
+    /// Only show joins and parts from people we mentioned or who mentioned
+    /// us in the last this many minutes. Zero shows everyone's.
+    pub presence_recent: u64,
     scripts: Vec<String>,
+    /// How many messages to keep in memory. Zero keeps everything.
+    pub scrollback: usize,
     server: String,
     pub text_size: u16,
+    pub theme: String,
+    /// How long a moderator timeout lasts, in seconds.
+    pub timeout_duration: u64,
     pub timestamp: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token_store: Option<String>,
+    /// Local color overrides keyed on the author's name. Tables have to come
+    /// after plain values in TOML, so keep these at the end.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub colors: BTreeMap<String, String>,
+    /// People whose messages are kept out of the log.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignored: Vec<Ignored>,
 }
 
 impl Configuration {
     pub fn load<P: AsRef<Path>>(path: P) -> Self {
         if path.as_ref().exists() {
-            let buffer = fs::read_to_string(path).unwrap();
-            toml::from_str(&buffer).unwrap()
+            Self::read(path).unwrap()
         } else {
             let config = Self::default();
-            config.save(path);
+            config.save(path).unwrap();
             config
         }
     }
 
-    pub fn save<P: AsRef<Path>>(&self, path: P) {
-        let buffer = toml::to_string_pretty(self).unwrap();
-        fs::write(path, buffer).unwrap();
+    /// Reads the configuration at `path`, rejecting values that would only
+    /// fail later on, like a timestamp format chrono can't use.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let buffer = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let config: Self = toml::from_str(&buffer).map_err(ConfigError::Parse)?;
+        if !valid_timestamp(&config.timestamp) {
+            return Err(ConfigError::Invalid(format!(
+                "\"{}\" is not a valid timestamp format",
+                config.timestamp
+            )));
+        }
+        Ok(config)
+    }
+
+    /// Writes the configuration to `path`. If the file already exists, only
+    /// the keys we know about are replaced so that comments and unknown keys
+    /// survive the round trip.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+        let fresh: Document = toml::to_string_pretty(self).unwrap().parse().unwrap();
+        let mut document = match fs::read_to_string(&path) {
+            Ok(buffer) => buffer.parse().map_err(ConfigError::Edit)?,
+            Err(_) => Document::new(),
+        };
+        for (key, item) in fresh.iter() {
+            match (document.get_mut(key), item) {
+                (Some(Item::Value(old)), Item::Value(new)) => {
+                    let decor = old.decor().clone();
+                    *old = new.clone();
+                    *old.decor_mut() = decor;
+                }
+                _ => document[key] = item.clone(),
+            }
+        }
+        for key in OPTIONAL_KEYS {
+            if !fresh.contains_key(key) {
+                document.remove(key);
+            }
+        }
+        fs::write(path, document.to_string()).map_err(ConfigError::Io)
     }
 
     pub fn scripts(&self) -> &Vec<String> {
@@ -54,16 +162,289 @@ impl Configuration {
     pub fn token(&self) -> &String {
         &self.token
     }
+
+    pub fn token_command(&self) -> Option<&String> {
+        self.token_command.as_ref()
+    }
+
+    pub fn token_file(&self) -> Option<&String> {
+        self.token_file.as_ref()
+    }
+
+    pub fn token_store(&self) -> Option<&String> {
+        self.token_store.as_ref()
+    }
+
+    /// Returns whether `other` gets its token from the same place as we do.
+    pub fn same_token_source(&self, other: &Self) -> bool {
+        self.token == other.token
+            && self.token_command == other.token_command
+            && self.token_file == other.token_file
+            && self.token_store == other.token_store
+    }
+
+    pub fn set_scripts(&mut self, scripts: Vec<String>) {
+        self.scripts = scripts;
+    }
+
+    pub fn set_server(&mut self, server: String) {
+        self.server = server;
+    }
+
+    pub fn set_token(&mut self, token: String) {
+        self.token = token;
+    }
+
+    pub fn set_token_store(&mut self, path: Option<String>) {
+        self.token_store = path;
+    }
+}
+
+/// How messages deleted by the server are shown.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletedMode {
+    /// Remove them from the log entirely.
+    Hide,
+    /// Leave a "message deleted" line behind. Moderators see the original
+    /// text struck through instead.
+    Placeholder,
+}
+
+impl DeletedMode {
+    pub const ALL: [DeletedMode; 2] = [DeletedMode::Hide, DeletedMode::Placeholder];
+}
+
+impl fmt::Display for DeletedMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeletedMode::Hide => write!(f, "Hide"),
+            DeletedMode::Placeholder => write!(f, "Show a placeholder"),
+        }
+    }
+}
+
+/// What to do with messages from ignored users.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IgnoredMode {
+    /// Leave one line behind saying who it was from.
+    Collapse,
+    /// Remove them from the log entirely.
+    Hide,
+}
+
+impl IgnoredMode {
+    pub const ALL: [IgnoredMode; 2] = [IgnoredMode::Collapse, IgnoredMode::Hide];
+}
+
+impl fmt::Display for IgnoredMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IgnoredMode::Collapse => write!(f, "Collapse"),
+            IgnoredMode::Hide => write!(f, "Hide"),
+        }
+    }
+}
+
+/// How messages are laid out in the log.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// One line per message, with the time and name in columns on the left.
+    Compact,
+    /// The name and time above the message, like most chat apps.
+    Cozy,
+    /// Like compact, but the time only shows when hovering over a message.
+    Hover,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 3] = [Layout::Compact, Layout::Cozy, Layout::Hover];
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Compact => write!(f, "Compact IRC"),
+            Layout::Cozy => write!(f, "Cozy"),
+            Layout::Hover => write!(f, "Timestamps on hover"),
+        }
+    }
+}
+
+/// How joins and parts show up in the log.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceMode {
+    /// One line per join or part.
+    All,
+    /// Runs of joins and parts share one line that expands on click.
+    Aggregate,
+    /// Leave them out.
+    Hide,
+}
+
+impl PresenceMode {
+    pub const ALL: [PresenceMode; 3] = [
+        PresenceMode::All,
+        PresenceMode::Aggregate,
+        PresenceMode::Hide,
+    ];
+}
+
+impl fmt::Display for PresenceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresenceMode::All => write!(f, "Show all"),
+            PresenceMode::Aggregate => write!(f, "Group them"),
+            PresenceMode::Hide => write!(f, "Hide"),
+        }
+    }
+}
+
+/// Someone on the ignore list. Names can change, so the ID is what counts
+/// when we have it. The name is used for people we haven't seen yet.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Ignored {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+    pub name: String,
+}
+
+impl Ignored {
+    pub fn matches(&self, author_id: usize, author: &str) -> bool {
+        match self.id {
+            Some(id) => id == author_id,
+            None => self.name == author,
+        }
+    }
+}
+
+impl fmt::Display for Ignored {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "{} (#{id})", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// What `migrate` did to bring a file up to date.
+pub struct Migration {
+    pub backup: PathBuf,
+    pub changes: Vec<String>,
+    pub from: i64,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Edit(toml_edit::TomlError),
+    Invalid(String),
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Edit(e) => write!(f, "{e}"),
+            ConfigError::Invalid(e) => write!(f, "{e}"),
+            ConfigError::Io(e) => write!(f, "{e}"),
+            ConfigError::Parse(e) => write!(f, "{e}"),
+        }
+    }
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
+            version: VERSION,
+            deleted: DeletedMode::Hide,
+            donation_pin: 60,
+            emotes: Vec::new(),
+            group_window: 300,
+            highlight_patterns: Vec::new(),
+            highlights: Vec::new(),
+            history: true,
+            history_lines: 200,
+            ignored_mode: IgnoredMode::Collapse,
+            layout: Layout::Compact,
+            message_limit: 500,
+            mute_duration: 600,
+            presence: PresenceMode::All,
+            presence_recent: 0,
             scripts: Vec::new(),
+            scrollback: 10_000,
             server: String::from("server.mattkc.com"),
             text_size: 16,
+            theme: String::from("dark"),
+            timeout_duration: 300,
             timestamp: String::from("%r "),
             token: String::from("Your token here"),
+            token_command: None,
+            token_file: None,
+            token_store: None,
+            colors: BTreeMap::new(),
+            ignored: Vec::new(),
+        }
+    }
+}
+
+/// Upgrades the file at `path` to the current schema version in place, leaving
+/// a copy of the original next to it. Files without a `version` key predate
+/// versioning and are treated as version 0. Returns `None` if there was nothing
+/// to do.
+pub fn migrate<P: AsRef<Path>>(path: P) -> Result<Option<Migration>, ConfigError> {
+    let path = path.as_ref();
+    let buffer = match fs::read_to_string(path) {
+        Ok(buffer) => buffer,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ConfigError::Io(e)),
+    };
+    let mut document: Document = buffer.parse().map_err(ConfigError::Edit)?;
+    let from = document
+        .get("version")
+        .and_then(Item::as_integer)
+        .unwrap_or(0);
+    if from >= VERSION {
+        return Ok(None);
+    }
+    let mut changes = Vec::new();
+    // Version 1 introduced the version key and made every other key optional.
+    // Write the defaults out anyway so they're easy to find and edit.
+    let defaults: Document = toml::to_string_pretty(&Configuration::default())
+        .unwrap()
+        .parse()
+        .unwrap();
+    for (key, item) in defaults.iter() {
+        if key != "version" && !OPTIONAL_KEYS.contains(&key) && !document.contains_key(key) {
+            document[key] = item.clone();
+            if let Some(value) = item.as_value() {
+                changes.push(format!("added {key} = {}", value.to_string().trim()));
+            }
         }
     }
+    document["version"] = toml_edit::value(VERSION);
+    changes.push(format!("set version = {VERSION}"));
+    let backup = path.with_extension(format!("v{from}.bak"));
+    fs::copy(path, &backup).map_err(ConfigError::Io)?;
+    fs::write(path, document.to_string()).map_err(ConfigError::Io)?;
+    Ok(Some(Migration {
+        backup,
+        changes,
+        from,
+    }))
+}
+
+/// Returns the last modification time of the file at `path`, if it can be
+/// determined.
+pub fn modified<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Returns whether `format` is a strftime string chrono can render. Formatting
+/// a timestamp with an invalid one panics, so check before using it.
+pub fn valid_timestamp(format: &str) -> bool {
+    !chrono::format::StrftimeItems::new(format).any(|item| item == chrono::format::Item::Error)
 }
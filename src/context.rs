@@ -0,0 +1,221 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! A wrapper that notices right clicks and long presses on what it holds.
+//! iced's own widgets only report left clicks, which the log already uses for
+//! links and names.
+
+use iced_native::{
+    event, layout, mouse, overlay, renderer, touch,
+    widget::{tree, Operation, Tree},
+    Clipboard, Element, Event, Layout, Length, Point, Rectangle, Shell, Widget,
+};
+use std::time::{Duration, Instant};
+
+/// How long a press has to be held to open the menu.
+const LONG_PRESS: Duration = Duration::from_millis(500);
+
+pub struct ContextArea<'a, Message, Renderer> {
+    content: Element<'a, Message, Renderer>,
+    on_menu: Option<Message>,
+}
+
+impl<'a, Message, Renderer> ContextArea<'a, Message, Renderer> {
+    pub fn new(content: impl Into<Element<'a, Message, Renderer>>) -> Self {
+        Self {
+            content: content.into(),
+            on_menu: None,
+        }
+    }
+
+    /// Sets the message for a right click or long press.
+    pub fn on_menu(mut self, message: Message) -> Self {
+        self.on_menu = Some(message);
+        self
+    }
+}
+
+/// When a press the content didn't handle started.
+#[derive(Default)]
+struct State {
+    pressed: Option<Instant>,
+}
+
+impl<'a, Message, Renderer> Widget<Message, Renderer> for ContextArea<'a, Message, Renderer>
+where
+    Message: Clone,
+    Renderer: iced_native::Renderer,
+{
+    fn tag(&self) -> tree::Tag {
+        tree::Tag::of::<State>()
+    }
+
+    fn state(&self) -> tree::State {
+        tree::State::new(State::default())
+    }
+
+    fn children(&self) -> Vec<Tree> {
+        vec![Tree::new(&self.content)]
+    }
+
+    fn diff(&self, tree: &mut Tree) {
+        tree.diff_children(std::slice::from_ref(&self.content))
+    }
+
+    fn width(&self) -> Length {
+        self.content.as_widget().width()
+    }
+
+    fn height(&self) -> Length {
+        self.content.as_widget().height()
+    }
+
+    fn layout(&self, renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        self.content.as_widget().layout(renderer, limits)
+    }
+
+    fn operate(
+        &self,
+        tree: &mut Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+        operation: &mut dyn Operation<Message>,
+    ) {
+        self.content
+            .as_widget()
+            .operate(&mut tree.children[0], layout, renderer, operation)
+    }
+
+    fn on_event(
+        &mut self,
+        tree: &mut Tree,
+        event: Event,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        renderer: &Renderer,
+        clipboard: &mut dyn Clipboard,
+        shell: &mut Shell<'_, Message>,
+    ) -> event::Status {
+        let status = self.content.as_widget_mut().on_event(
+            &mut tree.children[0],
+            event.clone(),
+            layout,
+            cursor_position,
+            renderer,
+            clipboard,
+            shell,
+        );
+        let Some(message) = &self.on_menu else {
+            return status;
+        };
+        let state = tree.state.downcast_mut::<State>();
+        let bounds = layout.bounds();
+        // Presses the content handled, like clicking a link, aren't ours.
+        let free = status == event::Status::Ignored;
+        let position = match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Right))
+                if bounds.contains(cursor_position) =>
+            {
+                shell.publish(message.clone());
+                return event::Status::Captured;
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                state.pressed = (free && bounds.contains(cursor_position)).then(Instant::now);
+                return status;
+            }
+            Event::Touch(touch::Event::FingerPressed { position, .. }) => {
+                state.pressed = (free && bounds.contains(position)).then(Instant::now);
+                return status;
+            }
+            Event::Mouse(mouse::Event::ButtonReleased(mouse::Button::Left)) => cursor_position,
+            Event::Touch(touch::Event::FingerLifted { position, .. }) => position,
+            Event::Touch(touch::Event::FingerLost { .. }) => {
+                state.pressed = None;
+                return status;
+            }
+            _ => return status,
+        };
+        let held = state
+            .pressed
+            .take()
+            .is_some_and(|pressed| pressed.elapsed() >= LONG_PRESS);
+        if held && bounds.contains(position) {
+            shell.publish(message.clone());
+            return event::Status::Captured;
+        }
+        status
+    }
+
+    fn mouse_interaction(
+        &self,
+        tree: &Tree,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        viewport: &Rectangle,
+        renderer: &Renderer,
+    ) -> mouse::Interaction {
+        self.content.as_widget().mouse_interaction(
+            &tree.children[0],
+            layout,
+            cursor_position,
+            viewport,
+            renderer,
+        )
+    }
+
+    fn draw(
+        &self,
+        tree: &Tree,
+        renderer: &mut Renderer,
+        theme: &Renderer::Theme,
+        style: &renderer::Style,
+        layout: Layout<'_>,
+        cursor_position: Point,
+        viewport: &Rectangle,
+    ) {
+        self.content.as_widget().draw(
+            &tree.children[0],
+            renderer,
+            theme,
+            style,
+            layout,
+            cursor_position,
+            viewport,
+        )
+    }
+
+    fn overlay<'b>(
+        &'b mut self,
+        tree: &'b mut Tree,
+        layout: Layout<'_>,
+        renderer: &Renderer,
+    ) -> Option<overlay::Element<'b, Message, Renderer>> {
+        self.content
+            .as_widget_mut()
+            .overlay(&mut tree.children[0], layout, renderer)
+    }
+}
+
+impl<'a, Message, Renderer> From<ContextArea<'a, Message, Renderer>>
+    for Element<'a, Message, Renderer>
+where
+    Message: 'a + Clone,
+    Renderer: 'a + iced_native::Renderer,
+{
+    fn from(area: ContextArea<'a, Message, Renderer>) -> Self {
+        Element::new(area)
+    }
+}
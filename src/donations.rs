@@ -0,0 +1,190 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// A donation shown above the log until `until`, or for as long as the client
+/// runs if that's too far off to represent.
+pub struct Pin {
+    pub amount: String,
+    pub author: String,
+    pub content: String,
+    until: Option<Instant>,
+}
+
+/// Everything donated since the client started. Amounts in different
+/// currencies are never added together, so everything is kept per currency.
+#[derive(Default)]
+pub struct Donations {
+    /// Each donor's total, by currency and then by author.
+    by_author: HashMap<String, HashMap<String, f64>>,
+    pinned: Vec<Pin>,
+    totals: HashMap<String, f64>,
+}
+
+impl Donations {
+    /// Returns who donated in `currency`, sorted from the most generous down.
+    pub fn leaderboard(&self, currency: &str) -> Vec<(&String, f64)> {
+        let mut donors: Vec<(&String, f64)> = self
+            .by_author
+            .get(currency)
+            .into_iter()
+            .flatten()
+            .map(|(author, amount)| (author, *amount))
+            .collect();
+        donors.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        donors
+    }
+
+    pub fn pinned(&self) -> &[Pin] {
+        &self.pinned
+    }
+
+    /// Drops pins that have been up for long enough.
+    pub fn prune(&mut self, now: Instant) {
+        self.pinned
+            .retain(|pin| pin.until.is_none_or(|until| until > now));
+    }
+
+    /// Adds a donation to the running totals and pins it for `pin` if that
+    /// isn't zero.
+    pub fn record(&mut self, author: &str, amount: &str, content: &str, pin: Duration) {
+        if let Some((currency, value)) = parse_amount(amount) {
+            *self
+                .by_author
+                .entry(currency.clone())
+                .or_default()
+                .entry(author.to_string())
+                .or_default() += value;
+            *self.totals.entry(currency).or_default() += value;
+        }
+        if !pin.is_zero() {
+            self.pinned.push(Pin {
+                amount: amount.to_string(),
+                author: author.to_string(),
+                content: content.to_string(),
+                until: Instant::now().checked_add(pin),
+            });
+        }
+    }
+
+    /// Returns how much was donated in each currency, sorted by currency.
+    pub fn totals(&self) -> Vec<(&String, f64)> {
+        let mut totals: Vec<(&String, f64)> = self
+            .totals
+            .iter()
+            .map(|(currency, total)| (currency, *total))
+            .collect();
+        totals.sort_by(|a, b| a.0.cmp(b.0));
+        totals
+    }
+}
+
+/// Writes `value` the way amounts in `currency` usually look, with symbols in
+/// front and codes behind, like "$5.00" or "5.00 EUR".
+pub fn format_amount(currency: &str, value: f64) -> String {
+    if currency.is_empty() {
+        format!("{value:.2}")
+    } else if currency.chars().any(char::is_alphanumeric) {
+        format!("{value:.2} {currency}")
+    } else {
+        format!("{currency}{value:.2}")
+    }
+}
+
+/// Returns the donation in `raw`, if there is one. The server sends an empty
+/// string or zero for messages without a donation.
+pub fn donation(raw: &str) -> Option<String> {
+    let raw = raw.trim();
+    match parse_amount(raw) {
+        Some((_, value)) if value > 0.0 => Some(raw.to_string()),
+        Some(_) => None,
+        None if raw.is_empty() => None,
+        None => Some(raw.to_string()),
+    }
+}
+
+/// Splits an amount like "$5.00" or "5,00 EUR" into its currency and value.
+/// The currency is whatever surrounds the number, and may be empty.
+fn parse_amount(raw: &str) -> Option<(String, f64)> {
+    let numeric = |c: char| c.is_ascii_digit() || c == '.' || c == ',';
+    let raw = raw.trim();
+    let start = raw.find(numeric)?;
+    let end = raw[start..]
+        .find(|c: char| !numeric(c))
+        .map_or(raw.len(), |i| start + i);
+    let currency = [raw[..start].trim(), raw[end..].trim()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let value = decimal(&raw[start..end]).parse().ok()?;
+    Some((currency, value))
+}
+
+/// Turns a number written with either decimal separator into one `parse`
+/// understands. When both show up the last one is the decimal point. A comma
+/// on its own is one when one or two digits follow it, like "5,5" or "5,00",
+/// and a thousands separator when three do. Several dots are all thousands
+/// separators.
+fn decimal(number: &str) -> String {
+    let point = match (number.rfind('.'), number.rfind(',')) {
+        (Some(dot), Some(comma)) => Some(dot.max(comma)),
+        (None, Some(comma)) if (1..=2).contains(&(number.len() - comma - 1)) => Some(comma),
+        (Some(dot), None) if number.matches('.').count() == 1 => Some(dot),
+        _ => None,
+    };
+    number
+        .char_indices()
+        .filter_map(|(i, c)| match c {
+            '.' | ',' if Some(i) == point => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_symbols_and_codes() {
+        assert_eq!(parse_amount("$5.00"), Some((String::from("$"), 5.0)));
+        assert_eq!(parse_amount("5,00 EUR"), Some((String::from("EUR"), 5.0)));
+    }
+
+    #[test]
+    fn reads_decimal_commas() {
+        assert_eq!(parse_amount("5,5 EUR"), Some((String::from("EUR"), 5.5)));
+        assert_eq!(parse_amount("1.234,56"), Some((String::new(), 1234.56)));
+    }
+
+    #[test]
+    fn reads_thousands_separators() {
+        assert_eq!(parse_amount("1,000"), Some((String::new(), 1000.0)));
+    }
+
+    #[test]
+    fn skips_empty_donations() {
+        assert_eq!(donation(""), None);
+        assert_eq!(donation("0"), None);
+        assert_eq!(donation("$5.00"), Some(String::from("$5.00")));
+    }
+}
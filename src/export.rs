@@ -0,0 +1,426 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Writes the log out as plain text, a standalone HTML page or JSON, so an
+//! excerpt of a conversation can be shared outside of the client.
+
+use crate::{markup, protocol::InboundData, theme, theme::Palette, Message};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use iced::{
+    widget::{button, column, pick_list, row, text, text_input},
+    Color, Element, Length,
+};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+const EXPORT_DIR: &str = "exports";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// A page that can be opened in any browser, with author colors.
+    Html,
+    /// The chat messages as the server sent them.
+    Json,
+    /// One line per message, like the log looks in the client.
+    Text,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Text, Format::Html, Format::Json];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Json => "json",
+            Format::Text => "txt",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Html => write!(f, "HTML"),
+            Format::Json => write!(f, "JSON"),
+            Format::Text => write!(f, "Plain text"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "html" => Ok(Format::Html),
+            "json" => Ok(Format::Json),
+            "text" | "txt" => Ok(Format::Text),
+            _ => Err(format!(
+                "\"{s}\" is not an export format, use text, html or json"
+            )),
+        }
+    }
+}
+
+/// The part of the log to export. Either end can be left open.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Range {
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+}
+
+impl Range {
+    /// Reads both ends of a range, where an empty string leaves that end
+    /// open.
+    pub fn parse(from: &str, to: &str) -> Result<Self, String> {
+        let end = |raw: &str| {
+            let raw = raw.trim();
+            if raw.is_empty() {
+                Ok(None)
+            } else {
+                parse_time(raw).map(Some).ok_or_else(|| {
+                    format!("\"{raw}\" is not a time, use HH:MM or YYYY-MM-DDTHH:MM")
+                })
+            }
+        };
+        Ok(Self {
+            from: end(from)?,
+            to: end(to)?,
+        })
+    }
+
+    fn contains(&self, time: DateTime<Local>) -> bool {
+        self.from.is_none_or(|from| time >= from) && self.to.is_none_or(|to| time <= to)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Cancel,
+    Export,
+    FormatChange(Format),
+    FromChange(String),
+    ToChange(String),
+}
+
+/// The state of the export screen.
+pub struct Exporter {
+    error: Option<String>,
+    format: Format,
+    from: String,
+    to: String,
+}
+
+impl Exporter {
+    pub fn new() -> Self {
+        Self {
+            error: None,
+            format: Format::Text,
+            from: String::new(),
+            to: String::new(),
+        }
+    }
+
+    /// Returns what the user asked to export.
+    pub fn request(&self) -> Result<(Format, Range), String> {
+        Ok((self.format, Range::parse(&self.from, &self.to)?))
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn update(&mut self, event: Event) {
+        match event {
+            Event::FormatChange(format) => self.format = format,
+            Event::FromChange(s) => self.from = s,
+            Event::ToChange(s) => self.to = s,
+            // Exporting and cancelling need the log, so they're handled by
+            // the caller.
+            Event::Cancel | Event::Export => {}
+        }
+    }
+
+    pub fn view(&self, text_size: u16, palette: &Palette) -> Element<'_, Event> {
+        let mut content = column![
+            text("Format").size(text_size),
+            pick_list(&Format::ALL[..], Some(self.format), Event::FormatChange)
+                .text_size(text_size),
+            text("From (HH:MM or YYYY-MM-DDTHH:MM, empty for the start of the log)")
+                .size(text_size),
+            text_input("", &self.from, Event::FromChange).size(text_size),
+            text("To (empty for the end of the log)").size(text_size),
+            text_input("", &self.to, Event::ToChange).size(text_size),
+        ]
+        .spacing(8)
+        .padding(16)
+        .width(Length::Fill);
+        if let Some(error) = &self.error {
+            content = content.push(text(error).style(palette.part).size(text_size));
+        }
+        content
+            .push(
+                row![
+                    button(text("Export").size(text_size)).on_press(Event::Export),
+                    button(text("Cancel").size(text_size)).on_press(Event::Cancel)
+                ]
+                .spacing(4),
+            )
+            .into()
+    }
+}
+
+/// Writes the messages in `range` to a new file in the exports directory and
+/// returns where it went. `color` picks the color an author is drawn in.
+/// What deleted messages said is only exported for moderators, the same as
+/// in the log.
+pub fn write<'a, I, F>(
+    messages: I,
+    format: Format,
+    range: Range,
+    moderator: bool,
+    timestamp: &str,
+    palette: &Palette,
+    color: F,
+) -> io::Result<PathBuf>
+where
+    I: IntoIterator<Item = &'a Message>,
+    F: Fn(&str, Option<Color>) -> Option<Color>,
+{
+    let messages = select(messages, range);
+    fs::create_dir_all(EXPORT_DIR)?;
+    let path = PathBuf::from(EXPORT_DIR).join(format!(
+        "chat-{}.{}",
+        Local::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    ));
+    let mut out = BufWriter::new(File::create(&path)?);
+    match format {
+        Format::Html => write_html(&mut out, &messages, moderator, timestamp, palette, color)?,
+        Format::Json => write_json(&mut out, &messages, moderator)?,
+        Format::Text => write_text(&mut out, &messages, moderator, timestamp)?,
+    }
+    out.flush()?;
+    Ok(path)
+}
+
+/// Picks the messages in `range`. Joins, parts and system messages don't
+/// have a time of their own, so they go with the chat message before them.
+/// Grouped joins and parts are split up again.
+fn select<'a, I: IntoIterator<Item = &'a Message>>(messages: I, range: Range) -> Vec<&'a Message> {
+    let mut last = None;
+    messages
+        .into_iter()
+        .flat_map(|message| match message {
+            Message::Presence { events, .. } => events.iter().collect(),
+            message => vec![message],
+        })
+        .filter(|message| {
+            if let Message::Normal { timestamp, .. } = message {
+                last = Some(*timestamp);
+            }
+            match last {
+                Some(time) => range.contains(time),
+                None => range.from.is_none(),
+            }
+        })
+        .collect()
+}
+
+fn write_text(
+    out: &mut impl Write,
+    messages: &[&Message],
+    moderator: bool,
+    timestamp: &str,
+) -> io::Result<()> {
+    for message in messages {
+        match message {
+            Message::Join(name) => writeln!(out, "+{name}")?,
+            Message::Leave(name) => writeln!(out, "-{name}")?,
+            Message::Normal {
+                author,
+                content,
+                deleted,
+                donation,
+                timestamp: time,
+                ..
+            } => {
+                write!(out, "{}{author}", time.format(timestamp))?;
+                if let Some(amount) = donation {
+                    write!(out, " donated {amount}")?;
+                }
+                match (deleted, moderator) {
+                    (true, true) => writeln!(out, ": {content} (deleted)")?,
+                    (true, false) => writeln!(out, ": message deleted")?,
+                    (false, _) => writeln!(out, ": {content}")?,
+                }
+            }
+            Message::System(lines) => writeln!(out, "* {}", markup::plain(lines))?,
+            // `select` splits these up.
+            Message::Presence { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_html<F: Fn(&str, Option<Color>) -> Option<Color>>(
+    out: &mut impl Write,
+    messages: &[&Message],
+    moderator: bool,
+    timestamp: &str,
+    palette: &Palette,
+    color: F,
+) -> io::Result<()> {
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(
+        out,
+        "<html><head><meta charset=\"utf-8\"><title>ChatKC log</title>"
+    )?;
+    writeln!(
+        out,
+        "<style>body {{ background: {}; color: {}; font-family: sans-serif; }} \
+         p {{ margin: 0.2em 0; }} .time {{ color: {}; }} .join {{ color: {}; }} \
+         .part {{ color: {}; }} .system {{ color: {}; }} .deleted {{ text-decoration: line-through; }} \
+         .donation {{ font-weight: bold; }}</style>",
+        theme::to_hex(palette.background),
+        theme::to_hex(palette.text),
+        theme::to_hex(palette.timestamp),
+        theme::to_hex(palette.join),
+        theme::to_hex(palette.part),
+        theme::to_hex(palette.system),
+    )?;
+    writeln!(out, "</head><body>")?;
+    for message in messages {
+        match message {
+            Message::Join(name) => writeln!(
+                out,
+                "<p class=\"join\">+{}</p>",
+                html_escape::encode_text(name)
+            )?,
+            Message::Leave(name) => writeln!(
+                out,
+                "<p class=\"part\">-{}</p>",
+                html_escape::encode_text(name)
+            )?,
+            Message::Normal {
+                author,
+                color: author_color,
+                deleted,
+                donation,
+                rich,
+                timestamp: time,
+                ..
+            } => {
+                let class = match (deleted, donation) {
+                    (true, _) => " class=\"deleted\"",
+                    (false, Some(_)) => " class=\"donation\"",
+                    (false, None) => "",
+                };
+                let style = color(author, *author_color)
+                    .map(|c| format!(" style=\"color: {}\"", theme::to_hex(c)))
+                    .unwrap_or_default();
+                write!(
+                    out,
+                    "<p{class}><span class=\"time\">{}</span><span{style}>{}</span>",
+                    html_escape::encode_text(&time.format(timestamp).to_string()),
+                    html_escape::encode_text(author)
+                )?;
+                if let Some(amount) = donation {
+                    write!(out, " donated {}", html_escape::encode_text(amount))?;
+                }
+                if *deleted && !moderator {
+                    writeln!(out, ": message deleted</p>")?;
+                } else {
+                    writeln!(out, ": {}</p>", markup::html(rich))?;
+                }
+            }
+            Message::System(lines) => {
+                writeln!(out, "<p class=\"system\">{}</p>", markup::html(lines))?
+            }
+            Message::Presence { .. } => {}
+        }
+    }
+    writeln!(out, "</body></html>")
+}
+
+/// Writes the chat messages in the same shape the server sends them in. The
+/// message text is the markup the client kept, not the server's raw HTML.
+/// Deleted messages are left out unless we're a moderator.
+fn write_json(out: &mut impl Write, messages: &[&Message], moderator: bool) -> io::Result<()> {
+    let chats: Vec<InboundData> = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Normal {
+                auth,
+                author,
+                author_id,
+                author_level,
+                color,
+                deleted,
+                donation,
+                id,
+                reply,
+                rich,
+                timestamp,
+                ..
+            } if !*deleted || moderator => Some(InboundData::Chat {
+                auth: *auth,
+                author: author.clone(),
+                author_color: color.map(theme::to_hex).unwrap_or_default(),
+                author_id: *author_id,
+                author_level: *author_level,
+                donate_value: donation.clone().unwrap_or_default(),
+                id: *id,
+                message: markup::html(rich),
+                reply: *reply,
+                time: timestamp.timestamp_millis() as usize,
+            }),
+            _ => None,
+        })
+        .collect();
+    serde_json::to_writer_pretty(&mut *out, &chats)?;
+    writeln!(out)
+}
+
+/// Reads a time like `14:30`, `14:30:15` or `2023-01-31T14:30`. Times without
+/// a date are taken to be today.
+fn parse_time(raw: &str) -> Option<DateTime<Local>> {
+    let naive = [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(raw, format).ok())
+    .or_else(|| {
+        let date = NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0));
+        date.or_else(|| {
+            ["%H:%M:%S", "%H:%M"]
+                .iter()
+                .find_map(|format| NaiveTime::parse_from_str(raw, format).ok())
+                .map(|time| Local::now().date_naive().and_time(time))
+        })
+    })?;
+    Local.from_local_datetime(&naive).earliest()
+}
@@ -0,0 +1,335 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Everything the server sends is appended to a JSON Lines file per server, so
+//! the log survives restarts. Reading goes backwards from the end of the file
+//! one page at a time, which keeps startup fast however long the file gets.
+//! Searches go through a word index kept next to the file, so they only read
+//! the records that could match, and run off the UI thread since bringing the
+//! index up to date can take a while.
+
+use crate::{
+    markup,
+    protocol::{InboundData, InboundMessage},
+    search::{self, Query},
+};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    future::Future,
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Bound,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+const CHUNK: u64 = 64 * 1024;
+const LOG_DIR: &str = "logs";
+
+/// A message as it's kept on disk.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Record {
+    #[serde(flatten)]
+    pub message: InboundMessage,
+    /// When we got the message, in milliseconds since the Unix epoch.
+    pub received: i64,
+}
+
+/// Which records each word appears in, by where the record starts in the
+/// file. Words are lowercase, taken from the author and the message text.
+#[derive(Default, Deserialize, Serialize)]
+struct Index {
+    /// How much of the file has been indexed.
+    indexed: u64,
+    words: BTreeMap<String, Vec<u64>>,
+}
+
+impl Index {
+    fn add(&mut self, offset: u64, record: &Record) {
+        if let Some(text) = searchable(record) {
+            for word in search::words(&text) {
+                self.words.entry(word).or_default().push(offset);
+            }
+        }
+    }
+
+    /// Returns the records that have a word starting with each of `words`, in
+    /// file order.
+    fn candidates(&self, words: &[String]) -> Vec<u64> {
+        let mut found: Option<Vec<u64>> = None;
+        for word in words {
+            let mut offsets: Vec<u64> = self
+                .words
+                .range::<str, _>((Bound::Included(word.as_str()), Bound::Unbounded))
+                .take_while(|(indexed, _)| indexed.starts_with(word.as_str()))
+                .flat_map(|(_, offsets)| offsets.iter().copied())
+                .collect();
+            offsets.sort_unstable();
+            offsets.dedup();
+            found = Some(match found {
+                Some(mut found) => {
+                    found.retain(|offset| offsets.binary_search(offset).is_ok());
+                    found
+                }
+                None => offsets,
+            });
+        }
+        found.unwrap_or_default()
+    }
+}
+
+pub struct History {
+    /// Where the oldest record we've handed out starts.
+    cursor: u64,
+    /// How long the file is.
+    end: u64,
+    file: File,
+    /// Only loaded once something is searched for. Searches hold the lock
+    /// while they run.
+    index: Arc<Mutex<Option<Index>>>,
+    path: PathBuf,
+}
+
+impl History {
+    pub fn open(server: &str) -> io::Result<Self> {
+        fs::create_dir_all(LOG_DIR)?;
+        let path = path(server);
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        let cursor = file.metadata()?.len();
+        Ok(Self {
+            cursor,
+            end: cursor,
+            file,
+            index: Arc::default(),
+            path,
+        })
+    }
+
+    pub fn append(&mut self, message: &InboundMessage) -> io::Result<()> {
+        let record = Record {
+            message: message.clone(),
+            received: Local::now().timestamp_millis(),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        let offset = self.end;
+        self.end += line.len() as u64;
+        // If a search has the index, it catches up on its own next time.
+        if let Ok(mut index) = self.index.try_lock() {
+            if let Some(index) = index.as_mut().filter(|index| index.indexed == offset) {
+                index.add(offset, &record);
+                index.indexed = self.end;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether everything in the file has been read.
+    pub fn exhausted(&self) -> bool {
+        self.cursor == 0
+    }
+
+    /// Reads up to `count` records from before the ones already read, oldest
+    /// first. Lines that can't be parsed are skipped.
+    pub fn older(&mut self, count: usize) -> io::Result<Vec<Record>> {
+        let mut file = File::open(&self.path)?;
+        let mut start = self.cursor;
+        let mut buffer = Vec::new();
+        while start > 0 && buffer.iter().filter(|b| **b == b'\n').count() <= count {
+            let from = start.saturating_sub(CHUNK);
+            let mut chunk = vec![0; (start - from) as usize];
+            file.seek(SeekFrom::Start(from))?;
+            file.read_exact(&mut chunk)?;
+            chunk.extend_from_slice(&buffer);
+            buffer = chunk;
+            start = from;
+        }
+        // The buffer ends on a line break, so splitting leaves an empty piece
+        // at the end. If we didn't reach the start of the file, the first
+        // piece is only part of a line.
+        let mut lines: Vec<&[u8]> = buffer.split(|b| *b == b'\n').collect();
+        lines.pop();
+        let skip = if start > 0 { 1 } else { 0 };
+        let lines = &lines[skip..];
+        let lines = &lines[lines.len().saturating_sub(count)..];
+        self.cursor -= lines.iter().map(|line| line.len() as u64 + 1).sum::<u64>();
+        Ok(lines
+            .iter()
+            .filter_map(|line| serde_json::from_slice(line).ok())
+            .collect())
+    }
+
+    /// Finds up to `limit` of the newest chat messages matching `query` that
+    /// haven't been read with `older` yet, oldest first.
+    pub fn search(
+        &self,
+        query: Query,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<Record>, String>> {
+        let search = Search {
+            cursor: self.cursor,
+            end: self.end,
+            index: self.index.clone(),
+            path: self.path.clone(),
+        };
+        async move {
+            match tokio::task::spawn_blocking(move || search.run(&query, limit)).await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+    }
+}
+
+/// A search through the file as it was when the search started.
+struct Search {
+    cursor: u64,
+    end: u64,
+    index: Arc<Mutex<Option<Index>>>,
+    path: PathBuf,
+}
+
+impl Search {
+    fn run(&self, query: &Query, limit: usize) -> io::Result<Vec<Record>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut found = VecDeque::new();
+        let Some(words) = query.words() else {
+            // Nothing to look up, so read everything.
+            let mut offset = 0;
+            let mut line = String::new();
+            while offset < self.cursor {
+                line.clear();
+                let read = reader.read_line(&mut line)?;
+                if read == 0 {
+                    break;
+                }
+                offset += read as u64;
+                if let Ok(record) = serde_json::from_str::<Record>(&line) {
+                    if matches(&record, query) {
+                        if found.len() == limit {
+                            found.pop_front();
+                        }
+                        found.push_back(record);
+                    }
+                }
+            }
+            return Ok(found.into());
+        };
+        let mut index = self.index.lock().unwrap_or_else(PoisonError::into_inner);
+        self.update_index(&mut index)?;
+        let candidates = index.as_ref().unwrap().candidates(&words);
+        drop(index);
+        let mut line = String::new();
+        for offset in candidates.into_iter().rev() {
+            if found.len() == limit {
+                break;
+            }
+            if offset >= self.cursor {
+                continue;
+            }
+            reader.seek(SeekFrom::Start(offset))?;
+            line.clear();
+            reader.read_line(&mut line)?;
+            if let Ok(record) = serde_json::from_str::<Record>(&line) {
+                if matches(&record, query) {
+                    found.push_front(record);
+                }
+            }
+        }
+        Ok(found.into())
+    }
+
+    /// Loads the index and brings it up to date with the file, saving it
+    /// again if anything was added.
+    fn update_index(&self, slot: &mut Option<Index>) -> io::Result<()> {
+        let path = self.path.with_extension("idx");
+        let mut index = match slot.take() {
+            Some(index) => index,
+            None => fs::read(&path)
+                .ok()
+                .and_then(|raw| serde_json::from_slice(&raw).ok())
+                .unwrap_or_default(),
+        };
+        if index.indexed > self.end {
+            // The file was replaced or cut short, so start over.
+            index = Index::default();
+        }
+        if index.indexed < self.end {
+            let mut reader = BufReader::new(File::open(&self.path)?);
+            reader.seek(SeekFrom::Start(index.indexed))?;
+            let mut offset = index.indexed;
+            let mut line = String::new();
+            while offset < self.end {
+                line.clear();
+                let read = reader.read_line(&mut line)?;
+                if read == 0 {
+                    break;
+                }
+                if let Ok(record) = serde_json::from_str::<Record>(&line) {
+                    index.add(offset, &record);
+                }
+                offset += read as u64;
+            }
+            index.indexed = offset;
+            let mut out = BufWriter::new(File::create(&path)?);
+            serde_json::to_writer(&mut out, &index)?;
+            out.flush()?;
+        }
+        *slot = Some(index);
+        Ok(())
+    }
+}
+
+/// Returns the text a record can be found by, if it's a chat message.
+fn searchable(record: &Record) -> Option<String> {
+    match record.message.data() {
+        InboundData::Chat {
+            author, message, ..
+        } => Some(format!(
+            "{author} {}",
+            markup::plain(&markup::parse(message))
+        )),
+        _ => None,
+    }
+}
+
+fn matches(record: &Record, query: &Query) -> bool {
+    match record.message.data() {
+        InboundData::Chat {
+            author, message, ..
+        } => query.is_match(author) || query.is_match(&markup::plain(&markup::parse(message))),
+        _ => false,
+    }
+}
+
+/// Picks the file for `server`, keeping only characters that are safe in a
+/// file name.
+fn path(server: &str) -> PathBuf {
+    let name: String = server
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    PathBuf::from(LOG_DIR).join(format!("{name}.jsonl"))
+}
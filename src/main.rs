@@ -14,41 +14,227 @@ GNU Affero General Public License for more details.
 You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>. */
 
+mod commands;
+mod complete;
+mod compose;
 mod config;
+mod context;
+mod donations;
+mod export;
+mod history;
+mod markup;
+mod mentions;
+mod moderation;
 mod protocol;
+mod scrollback;
+mod search;
+mod settings;
 mod socket;
+mod theme;
+mod token;
+mod users;
 
 use crate::{
-    config::Configuration,
-    protocol::{InboundData, MessageAuth, OutboundMessage},
+    commands::Registry,
+    complete::Completion,
+    compose::{Composer, Drafts},
+    config::{Configuration, DeletedMode, Ignored, IgnoredMode, Layout, PresenceMode},
+    context::ContextArea,
+    donations::Donations,
+    history::History,
+    markup::{Line, Span},
+    mentions::{Highlighter, Mention, Mentions},
+    moderation::Action,
+    protocol::{InboundData, InboundMessage, MessageAuth, OutboundMessage, UserStatus},
+    scrollback::Scrollback,
+    theme::Palette,
+    token::{Secret, TokenError},
+    users::Users,
 };
-use chrono::{DateTime, Local, TimeZone};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
 use iced::{
-    executor,
-    widget::{column, row, scrollable, text, text_input, Column},
+    alignment, executor, keyboard,
+    widget::{
+        button, column, container, row, scrollable, text, text_input, tooltip, Column, Row, Space,
+    },
     Application, Color, Command, Element, Length, Renderer, Settings, Subscription, Theme,
 };
+use iced_native::widget::{
+    self,
+    operation::{Focusable, Operation, Outcome},
+};
 use ketos::Interpreter;
 use once_cell::sync::Lazy;
-use std::path::Path;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+const CONFIG_PATH: &str = "config.toml";
+
+/// How tall a line of text is compared to the text size. It's only used to
+/// estimate how much room the rows outside of the view take up.
+const LINE_HEIGHT: f32 = 1.3;
+
+/// How many lines to build above and below the view so scrolling doesn't
+/// reveal empty space before the next redraw.
+const OVERSCAN: f32 = 20.0;
+
+/// How many characters wide the name column is in the compact layouts.
+const NAME_COLUMN: usize = 16;
+
+/// The most matches to show from history at once.
+const SEARCH_LIMIT: usize = 500;
 
+/// How far back Purge reaches, in minutes.
+const PURGE_WINDOW: i64 = 10;
+
+static MESSAGE_INPUT: Lazy<text_input::Id> = Lazy::new(text_input::Id::unique);
 static MESSAGE_LOG: Lazy<scrollable::Id> = Lazy::new(scrollable::Id::unique);
 
+/// What to copy out of a message.
+#[derive(Clone, Copy, Debug)]
+enum Clip {
+    Id,
+    /// The message with its time and author, the way the log shows it.
+    Line,
+    Text,
+}
+
 #[derive(Clone, Debug)]
 enum Event {
+    /// Up or Down was pressed. Only the message box uses them, to recall
+    /// sent messages.
+    Arrow {
+        older: bool,
+    },
+    /// Backs out of the ban or timeout waiting to be confirmed.
+    CancelModeration,
+    CloseLeaderboard,
+    CloseMentions,
+    CloseMenu,
+    /// Completes the word at the end of the message box, for Tab.
+    Complete,
+    /// Completes the word with one of the candidates.
+    CompleteWith(usize),
+    /// Goes ahead with the ban or timeout waiting to be confirmed.
+    ConfirmModeration,
+    /// Copies part of the message with this ID to the clipboard.
+    Copy(usize, Clip),
+    Escape,
+    Export(export::Event),
+    /// Shows only the messages from one person, or everyone again.
+    Filter(Option<String>),
+    /// Ignores someone, for the given number of seconds if there is one.
+    Ignore(Ignored, Option<u64>),
     InputChange(String),
+    JumpToBottom,
+    /// Scrolls to the chat message with this ID.
+    JumpToMessage(usize),
+    /// A key nothing else took, for picking messages from the keyboard.
+    LogKey(keyboard::KeyCode, keyboard::Modifiers),
+    Modifiers(keyboard::Modifiers),
+    /// Puts a mention of someone in the message box.
+    MentionUser(String),
+    Moderate(Action),
+    OpenExport,
+    OpenLeaderboard,
+    OpenLink(String),
+    OpenMentions,
+    OpenMenu(usize),
+    OpenSearch,
+    OpenSettings,
+    PassphraseChange(Secret),
+    /// Shows the next newer sent message in the message box.
+    RecallNewer,
+    /// Shows the next older sent message in the message box.
+    RecallOlder,
+    /// Starts a reply to the message with this ID in the message box.
+    ReplyTo(usize),
     SendMessage,
+    Resized(u32, u32),
+    Scrolled(scrollable::RelativeOffset),
+    Search(search::Event),
+    Settings(settings::Event),
     Socket(socket::Event),
+    Tick(Instant),
+    /// Expands or collapses a group of joins and parts.
+    TogglePresence(usize),
+    ToggleUserMenu(String),
+    ToggleUsers,
+    /// The token came back from [`token::resolve`], tagged with which lookup
+    /// it answers.
+    TokenResolved(u64, Result<MessageAuth, Arc<TokenError>>),
+    Unlock,
 }
 
 struct ElmKC {
-    auth: MessageAuth,
+    auth: Option<MessageAuth>,
+    auth_level: usize,
+    commands: Registry,
+    completion: Option<Completion>,
+    composer: Composer,
     config: Configuration,
-    input: String,
-    messages: Vec<Message>,
+    /// A ban or timeout waiting for the moderator to confirm it.
+    confirming: Option<Action>,
+    /// When we last mentioned each person or they mentioned us.
+    contacts: HashMap<String, Instant>,
+    config_modified: Option<SystemTime>,
+    donations: Donations,
+    drafts: Drafts,
+    exporter: Option<export::Exporter>,
+    /// Whose messages the log is limited to.
+    filter: Option<String>,
+    /// Whether the log is scrolled all the way down and should stay there.
+    following: bool,
+    highlighter: Highlighter,
+    history: Option<History>,
+    /// The newest chat message we have, whether it was loaded from the
+    /// history file or received. The server sends its backlog again whenever
+    /// we connect.
+    history_last: Option<usize>,
+    leaderboard: bool,
+    mentions: Mentions,
+    mentions_open: bool,
+    /// The chat message whose author menu is open.
+    menu: Option<usize>,
+    messages: Scrollback<Message>,
+    modifiers: keyboard::Modifiers,
+    /// People ignored for a while, and when that runs out.
+    muted: Vec<(Ignored, Instant)>,
+    palette: Palette,
+    passphrase: Option<String>,
+    /// The ID for the next group of joins and parts.
+    presence_id: usize,
+    /// Bumped to drop the connection and open a new one.
+    reconnects: usize,
     scripts: Vec<Interpreter>,
+    /// How far down the log is scrolled, from 0 to 1.
+    scroll: f32,
+    search: Option<search::Search>,
+    /// The message picked with j and k, by ID.
+    selected: Option<usize>,
+    settings: Option<settings::Settings>,
     socket: SocketState,
+    /// How many times we've gone looking for the token, so that a slow lookup
+    /// can't overwrite the answer to a newer one.
+    token_lookups: u64,
+    /// The passphrase being typed while the token store is locked.
+    unlock: Option<String>,
+    unread: usize,
+    /// Where the first message the user hasn't seen yet is in the log.
+    unread_marker: Option<usize>,
+    /// Whose actions are open in the user list.
+    user_menu: Option<String>,
+    /// The color of our name, as the server last told us.
+    user_color: Option<String>,
     username: Option<String>,
+    users: Users,
+    users_open: bool,
+    /// The size of the window, used to work out which rows are in view.
+    viewport: (u32, u32),
 }
 
 impl Application for ElmKC {
@@ -58,63 +244,498 @@ impl Application for ElmKC {
     type Theme = Theme;
 
     fn new(_flags: Self::Flags) -> (Self, Command<Self::Message>) {
-        let config = Configuration::load("config.toml");
-        let scripts = config
-            .scripts()
-            .iter()
-            .map(|path| {
-                let interp = Interpreter::new();
-                interp.run_file(Path::new(path)).unwrap();
-                interp
-            })
-            .collect();
-        (
-            Self {
-                auth: MessageAuth::Google {
-                    token: config.token().clone(),
-                },
-                config,
-                input: String::new(),
-                messages: Vec::new(),
-                scripts,
-                socket: SocketState::Disconnected,
-                username: None,
-            },
-            Command::none(),
-        )
+        let mut startup = Vec::new();
+        match config::migrate(CONFIG_PATH) {
+            Ok(Some(migration)) => startup.push(Message::system(format!(
+                "Migrated {CONFIG_PATH} from version {} to {} ({}), the old file was saved as {}",
+                migration.from,
+                config::VERSION,
+                migration.changes.join(", "),
+                migration.backup.display()
+            ))),
+            Ok(None) => {}
+            Err(e) => startup.push(Message::system(format!(
+                "Failed to migrate {CONFIG_PATH}: {e}"
+            ))),
+        }
+        let config = Configuration::load(CONFIG_PATH);
+        let palette = Palette::load(&config.theme).unwrap_or_else(|e| {
+            startup.push(Message::system(format!(
+                "Failed to load the {} theme: {e}",
+                config.theme
+            )));
+            Palette::default()
+        });
+        let scripts = load_scripts(config.scripts()).unwrap();
+        let (commands, errors) = Registry::new(&scripts);
+        startup.extend(errors.into_iter().map(Message::system));
+        let drafts = Drafts::load().unwrap_or_else(|e| {
+            startup.push(Message::system(format!("Failed to load drafts: {e}")));
+            Drafts::default()
+        });
+        let viewport = Settings::<()>::default().window.size;
+        let mut client = Self {
+            auth: None,
+            auth_level: 0,
+            commands,
+            completion: None,
+            composer: Composer::default(),
+            config: config.clone(),
+            confirming: None,
+            contacts: HashMap::new(),
+            config_modified: config::modified(CONFIG_PATH),
+            donations: Donations::default(),
+            drafts,
+            exporter: None,
+            filter: None,
+            following: true,
+            highlighter: Highlighter::default(),
+            history: None,
+            history_last: None,
+            leaderboard: false,
+            mentions: Mentions::default(),
+            mentions_open: false,
+            menu: None,
+            messages: Scrollback::new(config.scrollback),
+            modifiers: keyboard::Modifiers::default(),
+            muted: Vec::new(),
+            palette,
+            passphrase: None,
+            presence_id: 0,
+            reconnects: 0,
+            scripts,
+            scroll: 1.0,
+            search: None,
+            selected: None,
+            settings: None,
+            socket: SocketState::Disconnected,
+            token_lookups: 0,
+            unlock: None,
+            unread: 0,
+            unread_marker: None,
+            user_menu: None,
+            user_color: None,
+            username: None,
+            users: Users::default(),
+            users_open: false,
+            viewport,
+        };
+        for message in startup {
+            client.log(message);
+        }
+        client
+            .composer
+            .set_text(client.drafts.get(client.config.server()));
+        client.rebuild_highlighter();
+        client.open_history();
+        let resolve = client.resolve_auth();
+        (client, resolve)
     }
 
     fn subscription(&self) -> Subscription<Event> {
-        socket::connect(self.auth.clone(), self.config.server().clone()).map(Event::Socket)
+        let mut subscriptions = vec![
+            iced::time::every(Duration::from_secs(1)).map(Event::Tick),
+            iced::subscription::events_with(|event, status| match event {
+                iced::Event::Window(iced::window::Event::Resized { width, height }) => {
+                    Some(Event::Resized(width, height))
+                }
+                iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code: keyboard::KeyCode::F,
+                    modifiers,
+                }) if modifiers.command() => Some(Event::OpenSearch),
+                iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code: keyboard::KeyCode::Escape,
+                    ..
+                }) => Some(Event::Escape),
+                iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code: keyboard::KeyCode::Tab,
+                    ..
+                }) => Some(Event::Complete),
+                iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code: keyboard::KeyCode::Up,
+                    ..
+                }) => Some(Event::Arrow { older: true }),
+                iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code: keyboard::KeyCode::Down,
+                    ..
+                }) => Some(Event::Arrow { older: false }),
+                iced::Event::Keyboard(keyboard::Event::ModifiersChanged(modifiers)) => {
+                    Some(Event::Modifiers(modifiers))
+                }
+                // The message box takes typed keys while it has focus.
+                iced::Event::Keyboard(keyboard::Event::KeyPressed {
+                    key_code,
+                    modifiers,
+                }) if status == iced::event::Status::Ignored => {
+                    Some(Event::LogKey(key_code, modifiers))
+                }
+                _ => None,
+            }),
+        ];
+        if let Some(auth) = &self.auth {
+            subscriptions.push(
+                socket::connect(auth.clone(), self.config.server().clone(), self.reconnects)
+                    .map(Event::Socket),
+            );
+        }
+        Subscription::batch(subscriptions)
     }
 
     fn theme(&self) -> Self::Theme {
-        Theme::Dark
+        self.palette.theme()
     }
 
     fn title(&self) -> String {
-        if let Some(username) = &self.username {
+        let title = if let Some(username) = &self.username {
             format!("{username}@{} - ElmKC", self.config.server())
         } else {
             format!("{} - ElmKC", self.config.server())
+        };
+        match self.mentions.unread() {
+            0 => title,
+            unread => format!("({unread}) {title}"),
         }
     }
 
     fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
         match message {
+            Event::Arrow { older } => if_focused(
+                MESSAGE_INPUT.clone(),
+                if older {
+                    Event::RecallOlder
+                } else {
+                    Event::RecallNewer
+                },
+            ),
+            Event::CancelModeration => {
+                self.confirming = None;
+                Command::none()
+            }
+            Event::ConfirmModeration => {
+                if let Some(action) = self.confirming.take() {
+                    self.moderate(action);
+                }
+                Command::none()
+            }
+            Event::CloseLeaderboard => {
+                self.leaderboard = false;
+                Command::none()
+            }
+            Event::CloseMentions => {
+                self.mentions_open = false;
+                Command::none()
+            }
+            Event::Complete => {
+                if self.completion.is_none() {
+                    self.complete(true);
+                    match &self.completion {
+                        Some(completion) if completion.candidates.len() == 1 => {}
+                        _ => return Command::none(),
+                    }
+                }
+                self.accept_completion()
+            }
+            Event::CompleteWith(i) => {
+                if let Some(completion) = &mut self.completion {
+                    completion.selected = i;
+                }
+                self.accept_completion()
+            }
+            Event::Escape => {
+                if self.completion.take().is_some()
+                    || self.menu.take().is_some()
+                    || self.confirming.take().is_some()
+                {
+                    return Command::none();
+                }
+                if self.search.is_none() {
+                    self.selected = None;
+                }
+                self.update_search(search::Event::Close)
+            }
+            Event::CloseMenu => {
+                self.menu = None;
+                Command::none()
+            }
+            Event::Copy(id, clip) => {
+                self.menu = None;
+                let moderator = self.auth_level >= protocol::AUTH_MODERATOR;
+                let message = self.messages.iter().find(
+                    |message| matches!(message, Message::Normal { id: found, .. } if *found == id),
+                );
+                let Some(Message::Normal {
+                    author,
+                    content,
+                    deleted,
+                    timestamp,
+                    ..
+                }) = message
+                else {
+                    return Command::none();
+                };
+                // Only moderators get to see what deleted messages said.
+                let content = if *deleted && !moderator {
+                    "message deleted"
+                } else {
+                    content
+                };
+                iced::clipboard::write(match clip {
+                    Clip::Id => id.to_string(),
+                    Clip::Line => format!(
+                        "{}{author}: {content}",
+                        timestamp.format(&self.config.timestamp)
+                    ),
+                    Clip::Text => content.to_string(),
+                })
+            }
+            Event::Export(export::Event::Cancel) => {
+                self.exporter = None;
+                Command::none()
+            }
+            Event::Export(export::Event::Export) => {
+                if let Some(exporter) = &mut self.exporter {
+                    match exporter.request() {
+                        Ok((format, range)) => {
+                            self.exporter = None;
+                            self.export(format, range);
+                        }
+                        Err(e) => exporter.set_error(e),
+                    }
+                }
+                Command::none()
+            }
+            Event::Filter(filter) => {
+                self.filter = filter;
+                self.menu = None;
+                self.user_menu = None;
+                Command::none()
+            }
+            Event::Export(event) => {
+                if let Some(exporter) = &mut self.exporter {
+                    exporter.update(event);
+                }
+                Command::none()
+            }
+            Event::Ignore(user, duration) => {
+                self.menu = None;
+                self.user_menu = None;
+                self.ignore(user, duration);
+                self.follow()
+            }
             Event::InputChange(s) => {
-                self.input = s;
+                self.composer.set_input(s);
+                self.complete(false);
+                self.save_draft();
                 Command::none()
             }
-            Event::SendMessage => match &mut self.socket {
-                SocketState::Connected(connection) => {
-                    let payload = OutboundMessage::message(&self.auth, &self.input, None);
-                    connection.send(payload);
-                    self.input.clear();
-                    Command::none()
+            Event::JumpToBottom => {
+                self.following = true;
+                self.scroll = 1.0;
+                self.unread = 0;
+                self.follow()
+            }
+            Event::JumpToMessage(target) => {
+                self.mentions_open = false;
+                let found = self.messages.iter().position(
+                    |message| matches!(message, Message::Normal { id, .. } if *id == target),
+                );
+                match found {
+                    Some(i) => self.scroll_to(i),
+                    None => {
+                        self.log(Message::system("That message isn't in the log anymore"));
+                        self.follow()
+                    }
+                }
+            }
+            Event::MentionUser(name) => {
+                let input = &mut self.composer.input;
+                if !input.is_empty() && !input.ends_with(' ') {
+                    input.push(' ');
+                }
+                input.push_str(&format!("@{name} "));
+                self.save_draft();
+                self.menu = None;
+                self.user_menu = None;
+                Command::batch([
+                    text_input::focus(MESSAGE_INPUT.clone()),
+                    text_input::move_cursor_to_end(MESSAGE_INPUT.clone()),
+                ])
+            }
+            Event::Modifiers(modifiers) => {
+                self.modifiers = modifiers;
+                Command::none()
+            }
+            Event::Moderate(action @ (Action::Ban { .. } | Action::Timeout { .. })) => {
+                // These are hard to take back, so they wait for a second click.
+                self.confirming = Some(action);
+                Command::none()
+            }
+            Event::Moderate(action) => {
+                self.moderate(action);
+                Command::none()
+            }
+            Event::OpenExport => {
+                self.exporter = Some(export::Exporter::new());
+                Command::none()
+            }
+            Event::OpenLeaderboard => {
+                self.leaderboard = true;
+                Command::none()
+            }
+            Event::OpenLink(url) => match markup::open(&url) {
+                Ok(()) => Command::none(),
+                Err(e) => {
+                    self.log(Message::system(format!(
+                        "Failed to open {url} ({e}), so it was copied to the clipboard instead"
+                    )));
+                    iced::clipboard::write(url)
+                }
+            },
+            Event::OpenMentions => {
+                self.mentions_open = true;
+                self.mentions.read();
+                Command::none()
+            }
+            Event::LogKey(key_code, modifiers) => self.log_key(key_code, modifiers),
+            Event::OpenMenu(id) => {
+                self.menu = Some(id);
+                self.selected = Some(id);
+                Command::none()
+            }
+            Event::OpenSearch => {
+                if self.search.is_none() {
+                    self.search = Some(search::Search::new());
+                }
+                text_input::focus(search::INPUT.clone())
+            }
+            Event::OpenSettings => {
+                self.settings = Some(settings::Settings::new(&self.config));
+                Command::none()
+            }
+            Event::PassphraseChange(Secret(s)) => {
+                self.unlock = Some(s);
+                Command::none()
+            }
+            Event::RecallNewer | Event::RecallOlder => {
+                if self.unlock.is_some() {
+                    return Command::none();
+                }
+                let older = matches!(message, Event::RecallOlder);
+                if let Some(completion) = &mut self.completion {
+                    if older {
+                        completion.previous();
+                    } else {
+                        completion.next();
+                    }
+                    return Command::none();
+                }
+                if older {
+                    self.composer.older();
+                } else {
+                    self.composer.newer();
+                }
+                text_input::move_cursor_to_end(MESSAGE_INPUT.clone())
+            }
+            Event::ReplyTo(id) => {
+                self.menu = None;
+                let text = self.composer.text();
+                self.composer
+                    .set_text(&format!("/reply {id} {}", text.trim_start()));
+                self.save_draft();
+                Command::batch([
+                    text_input::focus(MESSAGE_INPUT.clone()),
+                    text_input::move_cursor_to_end(MESSAGE_INPUT.clone()),
+                ])
+            }
+            Event::SendMessage if self.completion.is_some() => self.accept_completion(),
+            Event::SendMessage if self.modifiers.shift() => {
+                self.composer.newline();
+                self.save_draft();
+                Command::none()
+            }
+            Event::SendMessage if self.composer.is_empty() => Command::none(),
+            Event::SendMessage => match self.commands.parse(&self.composer.text()) {
+                Ok(None) => {
+                    let text = self.composer.text();
+                    if self.send(commands::unescape(&text).to_string(), None) {
+                        self.composer.take();
+                        self.save_draft();
+                    }
+                    self.follow()
                 }
-                SocketState::Disconnected => Command::none(),
+                Ok(Some(command)) => {
+                    self.composer.take();
+                    self.save_draft();
+                    let command = self.run_command(command);
+                    Command::batch([command, self.follow()])
+                }
+                // Leave the command in the box so it can be fixed.
+                Err(e) => self.push(Message::system(e)),
             },
+            Event::Resized(width, height) => {
+                let columns_changed = width != self.viewport.0;
+                self.viewport = (width, height);
+                if columns_changed {
+                    let columns = self.columns();
+                    let layout = self.config.layout;
+                    self.messages
+                        .refresh(|message| rows(message, columns, layout));
+                }
+                Command::none()
+            }
+            Event::Scrolled(offset) => {
+                self.scroll = offset.y;
+                if offset.y <= 0.0 {
+                    return self.load_older();
+                }
+                // Leave a little slack for rounding.
+                self.following = offset.y >= 0.999;
+                if self.following {
+                    self.unread = 0;
+                }
+                Command::none()
+            }
+            Event::Search(event) => self.update_search(event),
+            Event::Settings(settings::Event::Cancel) => {
+                self.settings = None;
+                Command::none()
+            }
+            Event::Settings(settings::Event::Save) => {
+                if let Some(settings) = &mut self.settings {
+                    match settings.apply(&self.config) {
+                        Ok((config, staged)) => match config.save(CONFIG_PATH) {
+                            Ok(()) => {
+                                if let Some(Err(e)) = staged.map(token::Staged::commit) {
+                                    settings.set_error(format!("Failed to save the token: {e}"));
+                                    return Command::none();
+                                }
+                                if let Some(passphrase) = settings.passphrase() {
+                                    self.passphrase = Some(passphrase.to_string());
+                                }
+                                // Don't let the watcher report our own write as
+                                // a reload.
+                                self.config_modified = config::modified(CONFIG_PATH);
+                                self.settings = None;
+                                return self.apply_config(config);
+                            }
+                            Err(e) => {
+                                if let Some(staged) = staged {
+                                    staged.discard();
+                                }
+                                settings.set_error(format!("Failed to save {CONFIG_PATH}: {e}"))
+                            }
+                        },
+                        Err(e) => settings.set_error(e),
+                    }
+                }
+                Command::none()
+            }
+            Event::Settings(event) => {
+                if let Some(settings) = &mut self.settings {
+                    settings.update(event);
+                }
+                Command::none()
+            }
             Event::Socket(event) => match event {
                 socket::Event::Connected(connection) => {
                     self.socket = SocketState::Connected(connection);
@@ -122,169 +743,2507 @@ impl Application for ElmKC {
                 }
                 socket::Event::Disconnected => {
                     self.socket = SocketState::Disconnected;
+                    self.users.clear();
                     Command::none()
                 }
-                socket::Event::Received(message) => match message.data() {
-                    InboundData::Chat {
-                        author,
-                        author_color,
-                        message,
-                        id,
-                        time,
-                        ..
-                    } => {
-                        let color = if author_color.len() == 6 {
-                            if let Ok(raw) = u32::from_str_radix(&author_color, 16) {
-                                let red = ((raw & 0xFF0000) >> 16) as u8;
-                                let green = ((raw & 0xFF00) >> 8) as u8;
-                                let blue = (raw & 0xFF) as u8;
-                                Some(Color::from_rgb8(red, green, blue))
-                            } else {
-                                None
-                            }
-                        } else {
-                            None
-                        };
-                        let mut raw_content = String::new();
-                        html_escape::decode_html_entities_to_string(&message, &mut raw_content);
-                        // Am I doing this right? ~Bread
-                        let timestamp = Local.timestamp_millis_opt(*time as _).unwrap();
-                        self.messages.push(Message::Normal {
-                            author: author.clone(),
-                            color,
-                            content: raw_content,
-                            id: *id,
-                            timestamp,
-                        });
-                        scrollable::snap_to(MESSAGE_LOG.clone(), scrollable::RelativeOffset::END)
-                    }
-                    InboundData::Delete { messages } => {
-                        let mut victims = Vec::new();
-                        for i in 0..self.messages.len() {
-                            if let Message::Normal { id, .. } = self.messages[i] {
-                                if messages.contains(&id) {
-                                    victims.push(i);
-                                }
-                            }
-                        }
-                        // This is probably the correct way to handle this
-                        // since the indices might change, but I'm too tired to
-                        // think properly. ~Bread
-                        victims.sort();
-                        for i in (0..victims.len()).rev() {
-                            self.messages.remove(i);
+                socket::Event::Received(inbound) => {
+                    if let InboundData::Chat { id, .. } = inbound.data() {
+                        if self.history_last.is_some_and(|last| *id <= last) {
+                            // We already have this one, from the history file
+                            // or from before we reconnected.
+                            return Command::none();
                         }
-                        scrollable::snap_to(MESSAGE_LOG.clone(), scrollable::RelativeOffset::END)
-                    }
-                    InboundData::GetUserConf { name, .. } => {
-                        self.username = Some(name.clone());
-                        Command::none()
-                    }
-                    InboundData::Join { name } => {
-                        self.messages.push(Message::Join(name.clone()));
-                        scrollable::snap_to(MESSAGE_LOG.clone(), scrollable::RelativeOffset::END)
+                        self.history_last = Some(*id);
                     }
-                    InboundData::Part { name } => {
-                        self.messages.push(Message::Leave(name.clone()));
-                        scrollable::snap_to(MESSAGE_LOG.clone(), scrollable::RelativeOffset::END)
-                    }
-                    InboundData::ServerMsg { message } => {
-                        self.messages.push(Message::System(message.clone()));
-                        scrollable::snap_to(MESSAGE_LOG.clone(), scrollable::RelativeOffset::END)
-                    }
-                    _ => Command::none(),
-                },
+                    self.record_history(&inbound);
+                    self.receive(inbound.data())
+                }
             },
+            Event::ToggleUserMenu(name) => {
+                if self.user_menu.as_ref() == Some(&name) {
+                    self.user_menu = None;
+                } else {
+                    self.user_menu = Some(name);
+                }
+                Command::none()
+            }
+            Event::ToggleUsers => {
+                self.users_open = !self.users_open;
+                Command::none()
+            }
+            Event::TogglePresence(target) => {
+                let found = self.messages.iter().position(
+                    |message| matches!(message, Message::Presence { id, .. } if *id == target),
+                );
+                if let Some(i) = found {
+                    let columns = self.columns();
+                    let layout = self.config.layout;
+                    self.messages.amend(
+                        i,
+                        |message| {
+                            if let Message::Presence { expanded, .. } = message {
+                                *expanded = !*expanded;
+                            }
+                        },
+                        |message| rows(message, columns, layout),
+                    );
+                }
+                Command::none()
+            }
+            Event::TokenResolved(lookup, _) if lookup != self.token_lookups => Command::none(),
+            Event::TokenResolved(_, result) => {
+                self.token_resolved(result);
+                self.follow()
+            }
+            Event::Unlock => {
+                self.passphrase = self.unlock.take();
+                Command::batch([self.resolve_auth(), self.follow()])
+            }
+            Event::Tick(now) => {
+                self.donations.prune(now);
+                if let Err(e) = self.drafts.save() {
+                    self.log(Message::system(format!("Failed to save drafts: {e}")));
+                }
+                let (expired, muted): (Vec<_>, Vec<_>) = std::mem::take(&mut self.muted)
+                    .into_iter()
+                    .partition(|(_, until)| *until <= now);
+                self.muted = muted;
+                if !expired.is_empty() {
+                    for (user, _) in &expired {
+                        self.log(Message::system(format!("{} is no longer muted", user.name)));
+                    }
+                    self.apply_ignores();
+                }
+                self.watch_config()
+            }
         }
     }
 
     fn view(&self) -> Element<'_, Self::Message, Renderer<Self::Theme>> {
-        column![
-            scrollable(
-                Column::with_children(
-                    self.messages
-                        .iter()
-                        .cloned()
-                        .map(|msg| {
-                            match msg {
-                                Message::Join(name) => Element::from(
-                                    text(format!("+{name}"))
-                                        .size(self.config.text_size)
-                                        .style(Color::from_rgb8(178, 245, 178)),
-                                ),
-                                Message::Leave(name) => Element::from(
-                                    text(format!("-{name}"))
-                                        .size(self.config.text_size)
-                                        .style(Color::from_rgb8(245, 178, 178)),
-                                ),
-                                Message::Normal {
-                                    author,
-                                    color,
-                                    content,
-                                    timestamp,
-                                    ..
-                                } => {
-                                    let mut name = text(author);
-                                    if let Some(c) = color {
-                                        name = name.style(c);
-                                    }
-                                    Element::from(row![
-                                        text(timestamp.format(&self.config.timestamp))
-                                            .style(Color::from_rgb8(127, 127, 127))
-                                            .size(self.config.text_size),
-                                        name.size(self.config.text_size),
-                                        text(": ").size(self.config.text_size),
-                                        text(content).size(self.config.text_size)
-                                    ])
-                                }
-                                Message::System(content) => Element::from(Column::with_children(
-                                    content
-                                        .split("<br>")
-                                        .map(|t| {
-                                            text(t)
-                                                .style(Color::from_rgb8(127, 127, 127))
-                                                .size(self.config.text_size)
-                                        })
-                                        .map(Element::from)
-                                        .collect(),
-                                )),
-                            }
-                        })
-                        .collect()
+        if let Some(settings) = &self.settings {
+            return settings
+                .view(self.config.text_size, &self.palette)
+                .map(Event::Settings);
+        }
+        if let Some(exporter) = &self.exporter {
+            return exporter
+                .view(self.config.text_size, &self.palette)
+                .map(Event::Export);
+        }
+        if self.leaderboard {
+            return self.view_leaderboard();
+        }
+        if self.mentions_open {
+            return self.view_mentions();
+        }
+        let mut pinned = Column::new();
+        for pin in self.donations.pinned() {
+            pinned = pinned.push(
+                container(
+                    text(format!(
+                        "{} donated {}: {}",
+                        pin.author, pin.amount, pin.content
+                    ))
+                    .size(self.config.text_size),
                 )
                 .width(Length::Fill)
-            )
-            .id(MESSAGE_LOG.clone())
-            .height(Length::Fill),
-            text_input("Message", &self.input, |s| { Event::InputChange(s) })
-                .on_submit(Event::SendMessage)
+                .style(theme::donation()),
+            );
+        }
+        let line = self.line_height();
+        let total = self.messages.height() as f32;
+        let view = self.viewport.1 as f32 / line;
+        let first = self.scroll * (total - view).max(0.0);
+        let visible = self
+            .messages
+            .visible(first - OVERSCAN, view + OVERSCAN * 2.0);
+        let mut log = Column::new()
+            .width(Length::Fill)
+            .push(spacer(self.messages.top(visible.start) as f32 * line));
+        for i in visible.clone() {
+            let Some(message) = self.messages.get(i) else {
+                break;
+            };
+            if self.unread_marker == Some(i) {
+                log = log.push(
+                    text("──── new messages ────")
+                        .style(self.palette.part)
+                        .size(self.config.text_size)
+                        .width(Length::Fill)
+                        .horizontal_alignment(alignment::Horizontal::Center),
+                );
+            }
+            let row = self.view_message(message);
+            log = match self.search.as_ref().and_then(|search| search.highlight(i)) {
+                Some(current) => log.push(
+                    container(row)
+                        .width(Length::Fill)
+                        .style(theme::search_match(current)),
+                ),
+                None => log.push(row),
+            };
+        }
+        log = log.push(spacer(
+            (total - self.messages.top(visible.end) as f32) * line,
+        ));
+        let mut content = column![
+            row![
+                button(text("Settings").size(self.config.text_size)).on_press(Event::OpenSettings),
+                button(text("Donations").size(self.config.text_size))
+                    .on_press(Event::OpenLeaderboard),
+                button(text("Export").size(self.config.text_size)).on_press(Event::OpenExport),
+                button(
+                    text(match self.mentions.unread() {
+                        0 => String::from("Mentions"),
+                        unread => format!("Mentions ({unread})"),
+                    })
+                    .size(self.config.text_size)
+                )
+                .on_press(Event::OpenMentions),
+                button(text(format!("Users ({})", self.users.len())).size(self.config.text_size))
+                    .on_press(Event::ToggleUsers)
+            ]
+            .spacing(4),
+            pinned,
+        ];
+        if let Some(search) = &self.search {
+            content = content.push(
+                search
+                    .view(self.config.text_size, &self.palette, &self.config.timestamp)
+                    .map(Event::Search),
+            );
+        }
+        if let Some(name) = &self.filter {
+            content = content.push(self.view_filtered(name));
+        } else {
+            content = content.push(
+                scrollable(log)
+                    .id(MESSAGE_LOG.clone())
+                    .on_scroll(Event::Scrolled)
+                    .height(Length::Fill),
+            );
+        }
+        if let Some(action) = &self.confirming {
+            content = content.push(
+                row![
+                    text(match action {
+                        Action::Timeout { author, .. } => format!(
+                            "Time {author} out for {} seconds?",
+                            self.config.timeout_duration
+                        ),
+                        Action::Ban { author, .. } => format!("Ban {author}?"),
+                        _ => format!("Really {action}?"),
+                    })
+                    .size(self.config.text_size)
+                    .width(Length::Fill),
+                    button(text("Confirm").size(self.config.text_size))
+                        .on_press(Event::ConfirmModeration),
+                    button(text("Cancel").size(self.config.text_size))
+                        .on_press(Event::CancelModeration)
+                ]
+                .spacing(8)
+                .align_items(alignment::Alignment::Center),
+            );
+        }
+        if self.unread > 0 {
+            let label = if self.unread == 1 {
+                String::from("1 new message ↓")
+            } else {
+                format!("{} new messages ↓", self.unread)
+            };
+            content = content.push(
+                button(
+                    text(label)
+                        .size(self.config.text_size)
+                        .width(Length::Fill)
+                        .horizontal_alignment(alignment::Horizontal::Center),
+                )
+                .width(Length::Fill)
+                .on_press(Event::JumpToBottom),
+            );
+        }
+        let content = content
+            .push(if let Some(passphrase) = &self.unlock {
+                text_input("Passphrase for the token store", passphrase, |s| {
+                    Event::PassphraseChange(Secret(s))
+                })
+                .password()
+                .on_submit(Event::Unlock)
                 .size(self.config.text_size)
-        ]
-        .height(Length::Fill)
-        .width(Length::Fill)
-        .into()
+                .into()
+            } else {
+                self.view_composer()
+            })
+            .height(Length::Fill)
+            .width(Length::Fill);
+        if self.users_open {
+            row![content, self.view_users()].into()
+        } else {
+            content.into()
+        }
     }
 }
 
-#[derive(Clone)]
-enum Message {
-    Join(String),
-    Leave(String),
-    Normal {
-        author: String,
-        color: Option<Color>,
-        content: String,
-        id: usize,
-        timestamp: DateTime<Local>,
-    },
-    System(String),
-}
+impl ElmKC {
+    /// Loads the next page of history above what's in the log, keeping the
+    /// rows in view where they are.
+    fn load_older(&mut self) -> Command<Event> {
+        let view = self.viewport.1 as f32 / self.line_height();
+        let first = self.scroll * (self.messages.height() as f32 - view).max(0.0);
+        let before = self.messages.height();
+        if self.load_history() == 0 {
+            return Command::none();
+        }
+        let added = (self.messages.height() - before) as f32;
+        let scrollable = (self.messages.height() as f32 - view).max(1.0);
+        self.scroll = ((first + added) / scrollable).min(1.0);
+        scrollable::snap_to(
+            MESSAGE_LOG.clone(),
+            scrollable::RelativeOffset {
+                x: 0.0,
+                y: self.scroll,
+            },
+        )
+    }
 
-enum SocketState {
-    Connected(socket::Connection),
-    Disconnected,
-}
+    /// Reads a page of history into the top of the log and returns how many
+    /// messages it added. Deletions are only applied to messages in the same
+    /// page.
+    fn load_history(&mut self) -> usize {
+        let Some(history) = &mut self.history else {
+            return 0;
+        };
+        // Stop once the log is full, rather than letting old pages push it
+        // past its limit.
+        let count = self
+            .messages
+            .room()
+            .map_or(self.config.history_lines, |room| {
+                room.min(self.config.history_lines)
+            });
+        if history.exhausted() || count == 0 {
+            return 0;
+        }
+        let records = match history.older(count) {
+            Ok(records) => records,
+            Err(e) => {
+                self.history = None;
+                self.log(Message::system(format!(
+                    "Failed to read the chat history: {e}"
+                )));
+                return 0;
+            }
+        };
+        let deleted: Vec<usize> = records
+            .iter()
+            .filter_map(|record| match record.message.data() {
+                InboundData::Delete { messages } => Some(messages.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let columns = self.columns();
+        let layout = self.config.layout;
+        let mut page = Vec::new();
+        for record in &records {
+            let Some(mut message) = Message::from_inbound(record.message.data()) else {
+                continue;
+            };
+            if let Message::Normal {
+                author,
+                author_id,
+                content,
+                deleted: gone,
+                id,
+                ignored,
+                mention,
+                ..
+            } = &mut message
+            {
+                *mention = self.mentions_me(author, content);
+                if self.history_last.is_none_or(|last| *id > last) {
+                    self.history_last = Some(*id);
+                }
+                if deleted.contains(id) {
+                    match self.config.deleted {
+                        DeletedMode::Hide => continue,
+                        DeletedMode::Placeholder => *gone = true,
+                    }
+                }
+                if self.is_ignored(*author_id, author) {
+                    match self.config.ignored_mode {
+                        IgnoredMode::Collapse => *ignored = true,
+                        IgnoredMode::Hide => continue,
+                    }
+                }
+            }
+            page.push(message);
+        }
+        let page: Vec<(Message, u32)> = self
+            .group_presence(page)
+            .into_iter()
+            .map(|message| {
+                let height = rows(&message, columns, layout);
+                (message, height)
+            })
+            .collect();
+        let added = self.messages.prepend(page);
+        self.mark_groups();
+        self.unread_marker = self.unread_marker.map(|i| i + added);
+        self.refresh_search();
+        added
+    }
 
-fn main() -> iced::Result {
-    ElmKC::run(Settings::default())
+    /// Starts keeping history for the current server and loads the most
+    /// recent part of it.
+    fn open_history(&mut self) {
+        self.history = None;
+        self.history_last = None;
+        if !self.config.history {
+            return;
+        }
+        match History::open(self.config.server()) {
+            Ok(history) => {
+                self.history = Some(history);
+                self.load_history();
+            }
+            Err(e) => self.log(Message::system(format!(
+                "Failed to open the chat history: {e}"
+            ))),
+        }
+    }
+
+    fn record_history(&mut self, message: &InboundMessage) {
+        if let Some(history) = &mut self.history {
+            if let Err(e) = history.append(message) {
+                self.history = None;
+                self.log(Message::system(format!(
+                    "Failed to write to the chat history, so it's turned off: {e}"
+                )));
+            }
+        }
+    }
+
+    /// Handles something the server sent.
+    fn receive(&mut self, data: &InboundData) -> Command<Event> {
+        match data {
+            InboundData::Chat {
+                author,
+                author_color,
+                author_id,
+                author_level,
+                ..
+            } => {
+                self.users.spoke(
+                    author,
+                    *author_id,
+                    *author_level,
+                    theme::parse_color(author_color),
+                );
+                let message = Message::from_inbound(data).unwrap();
+                if let Message::Normal {
+                    author_id,
+                    content,
+                    donation: Some(amount),
+                    ..
+                } = &message
+                {
+                    if self.is_ignored(*author_id, author) {
+                        return self.push(message);
+                    }
+                    self.donations.record(
+                        author,
+                        amount,
+                        content,
+                        Duration::from_secs(self.config.donation_pin),
+                    );
+                }
+                self.push(message)
+            }
+            InboundData::AuthLevel { value } => {
+                self.auth_level = *value;
+                Command::none()
+            }
+            InboundData::Delete { messages } => {
+                let ids: Vec<String> = messages.iter().map(usize::to_string).collect();
+                self.record_moderation(format!("server deleted messages {}", ids.join(", ")));
+                if let (Some(marker), DeletedMode::Hide) = (self.unread_marker, self.config.deleted)
+                {
+                    let doomed = |message: &Message| match message {
+                        Message::Normal { id, .. } => messages.contains(id),
+                        _ => false,
+                    };
+                    let removed = self
+                        .messages
+                        .iter()
+                        .take(marker)
+                        .filter(|m| doomed(m))
+                        .count();
+                    // Ignored messages never counted as unread in the first place.
+                    let unread = self
+                        .messages
+                        .iter()
+                        .skip(marker)
+                        .filter(|m| {
+                            doomed(m) && !matches!(m, Message::Normal { ignored: true, .. })
+                        })
+                        .count();
+                    self.unread = self.unread.saturating_sub(unread);
+                    self.unread_marker = (self.unread > 0).then_some(marker - removed);
+                }
+                let columns = self.columns();
+                let layout = self.config.layout;
+                delete_messages(
+                    &mut self.messages,
+                    messages,
+                    self.config.deleted,
+                    columns,
+                    layout,
+                );
+                self.mark_groups();
+                self.refresh_search();
+                self.follow()
+            }
+            InboundData::GetUserConf { color, name } => {
+                self.user_color = Some(color.clone());
+                if self.username.as_ref() != Some(name) {
+                    self.username = Some(name.clone());
+                    self.rebuild_highlighter();
+                }
+                Command::none()
+            }
+            InboundData::Join { name } => {
+                self.users.join(name);
+                self.push(Message::from_inbound(data).unwrap())
+            }
+            InboundData::Part { name } => {
+                self.users.part(name);
+                if self.user_menu.as_ref() == Some(name) {
+                    self.user_menu = None;
+                }
+                self.push(Message::from_inbound(data).unwrap())
+            }
+            InboundData::ServerMsg { .. } => self.push(Message::from_inbound(data).unwrap()),
+            InboundData::Status { status } => self.status(status),
+            _ => Command::none(),
+        }
+    }
+
+    /// Keeps the log pinned to the bottom, unless the user scrolled away from
+    /// it to read something.
+    fn follow(&self) -> Command<Event> {
+        if self.following {
+            scrollable::snap_to(MESSAGE_LOG.clone(), scrollable::RelativeOffset::END)
+        } else {
+            Command::none()
+        }
+    }
+
+    /// Returns roughly how many characters fit on one line of the log.
+    fn columns(&self) -> usize {
+        (self.viewport.0 as f32 / (self.config.text_size as f32 * 0.5)).max(20.0) as usize
+    }
+
+    fn line_height(&self) -> f32 {
+        self.config.text_size as f32 * LINE_HEIGHT
+    }
+
+    /// Adds a message to the log, counting it as unread if the user isn't
+    /// looking at the bottom of the log.
+    fn log(&mut self, mut message: Message) {
+        if let Message::Normal {
+            author,
+            author_id,
+            content,
+            id,
+            ignored,
+            mention,
+            timestamp,
+            ..
+        } = &mut message
+        {
+            if self.is_ignored(*author_id, author) {
+                if self.config.ignored_mode == IgnoredMode::Hide {
+                    return;
+                }
+                *ignored = true;
+            } else if self.mentions_me(author, content) {
+                *mention = true;
+                self.contacts.insert(author.clone(), Instant::now());
+                self.mentions.record(Mention {
+                    author: author.clone(),
+                    content: content.clone(),
+                    id: *id,
+                    timestamp: *timestamp,
+                });
+            } else if self.username.as_ref() == Some(author) {
+                self.note_mentions(content.clone());
+            }
+        }
+        if let Message::Join(name) | Message::Leave(name) = &message {
+            if !self.shows_presence(name) {
+                return;
+            }
+            match self.config.presence {
+                PresenceMode::All => {}
+                PresenceMode::Aggregate => {
+                    let last = self.messages.len().wrapping_sub(1);
+                    if let Some(Message::Presence { .. }) = self.messages.get(last) {
+                        let columns = self.columns();
+                        let layout = self.config.layout;
+                        self.messages.amend(
+                            last,
+                            |group| {
+                                if let Message::Presence { events, .. } = group {
+                                    events.push(message);
+                                }
+                            },
+                            |group| rows(group, columns, layout),
+                        );
+                        return;
+                    }
+                    message = Message::Presence {
+                        events: vec![message],
+                        expanded: false,
+                        id: self.next_presence_id(),
+                    };
+                }
+                PresenceMode::Hide => return,
+            }
+        }
+        Grouping::after(self.messages.iter()).mark(&mut message, self.config.group_window);
+        let counts = !matches!(message, Message::Normal { ignored: true, .. });
+        let height = rows(&message, self.columns(), self.config.layout);
+        let found = self
+            .search
+            .as_ref()
+            .and_then(|search| search.query.as_ref())
+            .is_some_and(|query| found(query, &message));
+        if self.messages.push(message, height).is_some() {
+            // The oldest message fell off, so everything moved up by one.
+            self.unread_marker = self.unread_marker.and_then(|i| i.checked_sub(1));
+            if let Some(search) = &mut self.search {
+                if search.matches.first() == Some(&0) {
+                    search.matches.remove(0);
+                    search.current = search.current.and_then(|i| i.checked_sub(1));
+                }
+                for i in &mut search.matches {
+                    *i -= 1;
+                }
+            }
+        }
+        if found {
+            let last = self.messages.len() - 1;
+            if let Some(search) = &mut self.search {
+                search.matches.push(last);
+            }
+        }
+        if !self.following && counts {
+            if self.unread == 0 {
+                self.unread_marker = Some(self.messages.len() - 1);
+            }
+            self.unread += 1;
+        }
+    }
+
+    /// Adds a message to the log and scrolls down to it if we're following.
+    fn push(&mut self, message: Message) -> Command<Event> {
+        self.log(message);
+        self.follow()
+    }
+
+    /// Reloads the configuration if the file changed since we last looked.
+    fn watch_config(&mut self) -> Command<Event> {
+        let modified = config::modified(CONFIG_PATH);
+        if modified == self.config_modified {
+            return Command::none();
+        }
+        self.config_modified = modified;
+        match Configuration::read(CONFIG_PATH) {
+            Ok(config) if config == self.config => return Command::none(),
+            Ok(config) => {
+                let resolve = self.apply_config(config);
+                self.log(Message::system(format!("Reloaded {CONFIG_PATH}")));
+                return Command::batch([resolve, self.follow()]);
+            }
+            Err(e) => self.log(Message::system(format!(
+                "Failed to reload {CONFIG_PATH}: {e}"
+            ))),
+        }
+        self.follow()
+    }
+
+    fn view_leaderboard(&self) -> Element<'_, Event> {
+        let totals = self.donations.totals();
+        let summary = if totals.is_empty() {
+            donations::format_amount("", 0.0)
+        } else {
+            totals
+                .iter()
+                .map(|(currency, total)| donations::format_amount(currency, *total))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut board =
+            column![text(format!("Donated this session: {summary}")).size(self.config.text_size)]
+                .spacing(4)
+                .padding(16);
+        // Each currency gets its own ranking, since they can't be compared.
+        for (currency, _) in totals {
+            for (rank, (author, amount)) in
+                self.donations.leaderboard(currency).into_iter().enumerate()
+            {
+                board = board.push(
+                    text(format!(
+                        "{}. {author} {}",
+                        rank + 1,
+                        donations::format_amount(currency, amount)
+                    ))
+                    .size(self.config.text_size),
+                );
+            }
+        }
+        column![
+            scrollable(board).height(Length::Fill),
+            button(text("Back").size(self.config.text_size)).on_press(Event::CloseLeaderboard)
+        ]
+        .into()
+    }
+
+    /// Lays out the message box, with any lines waiting to be sent above it
+    /// and a count of how long the message is beside it.
+    fn view_composer(&self) -> Element<'_, Event> {
+        let length = self.composer.text().chars().count();
+        let limit = self.config.message_limit;
+        let color = if limit != 0 && length > limit {
+            self.palette.part
+        } else if limit != 0 && length * 10 >= limit * 9 {
+            self.palette.highlight
+        } else {
+            self.palette.timestamp
+        };
+        let counter = if limit == 0 {
+            length.to_string()
+        } else {
+            format!("{length}/{limit}")
+        };
+        let mut composer = Column::new();
+        if let Some(completion) = &self.completion {
+            for (i, candidate) in completion.candidates.iter().enumerate() {
+                let style = if i == completion.selected {
+                    iced::theme::Button::Primary
+                } else {
+                    iced::theme::Button::Text
+                };
+                composer = composer.push(
+                    button(text(candidate).size(self.config.text_size))
+                        .padding(2)
+                        .style(style)
+                        .on_press(Event::CompleteWith(i)),
+                );
+            }
+        }
+        for line in &self.composer.lines {
+            composer = composer.push(text(line).size(self.config.text_size));
+        }
+        composer = composer.push(
+            row![
+                text_input("Message", &self.composer.input, Event::InputChange)
+                    .id(MESSAGE_INPUT.clone())
+                    .on_submit(Event::SendMessage)
+                    .size(self.config.text_size),
+                text(counter).style(color).size(self.config.text_size)
+            ]
+            .spacing(4)
+            .align_items(iced::Alignment::Center),
+        );
+        composer.into()
+    }
+
+    /// Lays out only the messages from `name`. There are usually few enough
+    /// of them to skip the virtualized log.
+    fn view_filtered(&self, name: &str) -> Element<'_, Event> {
+        let mut log = Column::new().width(Length::Fill);
+        for message in self.messages.iter() {
+            if matches!(message, Message::Normal { author, .. } if author == name) {
+                log = log.push(self.view_message(message));
+            }
+        }
+        column![
+            row![
+                text(format!("Only showing messages from {name}"))
+                    .style(self.palette.system)
+                    .size(self.config.text_size)
+                    .width(Length::Fill),
+                button(text("Show everyone").size(self.config.text_size))
+                    .on_press(Event::Filter(None))
+            ],
+            scrollable(log).height(Length::Fill),
+        ]
+        .height(Length::Fill)
+        .into()
+    }
+
+    fn view_mentions(&self) -> Element<'_, Event> {
+        let mut list = Column::new().spacing(4).padding(16);
+        for mention in self.mentions.iter().rev() {
+            list = list.push(
+                button(
+                    text(format!(
+                        "{}{}: {}",
+                        mention.timestamp.format(&self.config.timestamp),
+                        mention.author,
+                        mention.content
+                    ))
+                    .size(self.config.text_size),
+                )
+                .width(Length::Fill)
+                .style(iced::theme::Button::Text)
+                .on_press(Event::JumpToMessage(mention.id)),
+            );
+        }
+        column![
+            scrollable(list).height(Length::Fill),
+            button(text("Back").size(self.config.text_size)).on_press(Event::CloseMentions)
+        ]
+        .into()
+    }
+
+    fn view_users(&self) -> Element<'_, Event> {
+        let mut list = column![text(format!("{} here", self.users.len()))
+            .style(self.palette.system)
+            .size(self.config.text_size)]
+        .spacing(2)
+        .padding(4);
+        for user in self.users.sorted() {
+            let mut label = Row::new();
+            if let Some(badge) = self.palette.badge(user.level) {
+                label = label.push(
+                    text(&badge.glyph)
+                        .style(badge.color)
+                        .size(self.config.text_size),
+                );
+            }
+            let mut name = text(&user.name).size(self.config.text_size);
+            if let Some(color) = self.author_color(&user.name, user.color) {
+                name = name.style(color);
+            }
+            list = list.push(
+                button(label.push(name))
+                    .padding(0)
+                    .style(iced::theme::Button::Text)
+                    .on_press(Event::ToggleUserMenu(user.name.clone())),
+            );
+            if self.user_menu.as_ref() == Some(&user.name) {
+                let target = Ignored {
+                    id: user.id,
+                    name: user.name.clone(),
+                };
+                let action = |label: &str, event| {
+                    button(text(label).size(self.config.text_size))
+                        .width(Length::Fill)
+                        .on_press(event)
+                        .padding(2)
+                };
+                list = list.push(
+                    column![
+                        action("Mention", Event::MentionUser(user.name.clone())),
+                        action(
+                            "Only their messages",
+                            Event::Filter(Some(user.name.clone()))
+                        ),
+                        action("Ignore", Event::Ignore(target.clone(), None)),
+                        action(
+                            "Mute",
+                            Event::Ignore(target, Some(self.config.mute_duration))
+                        ),
+                    ]
+                    .spacing(2)
+                    .padding([0, 0, 0, 8]),
+                );
+            }
+        }
+        container(scrollable(list).height(Length::Fill))
+            .width(Length::Units(200))
+            .height(Length::Fill)
+            .into()
+    }
+
+    fn view_message(&self, message: &Message) -> Element<'_, Event> {
+        match message {
+            Message::Join(name) => Element::from(
+                text(format!("+{name}"))
+                    .size(self.config.text_size)
+                    .style(self.palette.join),
+            ),
+            Message::Leave(name) => Element::from(
+                text(format!("-{name}"))
+                    .size(self.config.text_size)
+                    .style(self.palette.part),
+            ),
+            Message::Presence {
+                events,
+                expanded,
+                id,
+            } => {
+                let summary = button(
+                    text(presence_summary(events))
+                        .size(self.config.text_size)
+                        .style(self.palette.join),
+                )
+                .padding(0)
+                .style(iced::theme::Button::Text)
+                .on_press(Event::TogglePresence(*id));
+                if *expanded {
+                    Column::with_children(
+                        std::iter::once(summary.into())
+                            .chain(events.iter().map(|event| self.view_message(event)))
+                            .collect(),
+                    )
+                    .padding([0, 0, 0, 16])
+                    .into()
+                } else {
+                    summary.into()
+                }
+            }
+            Message::Normal {
+                author,
+                ignored: true,
+                new_day,
+                timestamp,
+                ..
+            } => self.view_day(
+                *new_day,
+                timestamp,
+                row![
+                    text(timestamp.format(&self.config.timestamp))
+                        .style(self.palette.timestamp)
+                        .size(self.config.text_size),
+                    text(format!("message from {author} (ignored)"))
+                        .style(self.palette.system)
+                        .size(self.config.text_size)
+                ]
+                .into(),
+            ),
+            Message::Normal {
+                auth,
+                author,
+                author_id,
+                author_level,
+                color,
+                content,
+                continued,
+                deleted,
+                donation,
+                id,
+                mention,
+                new_day,
+                rich,
+                timestamp,
+                ..
+            } => {
+                let moderator = self.auth_level >= protocol::AUTH_MODERATOR;
+                let content = if !deleted {
+                    self.view_markup(rich, None)
+                } else if moderator {
+                    text(strike(content))
+                        .style(self.palette.system)
+                        .size(self.config.text_size)
+                        .width(Length::Fill)
+                        .into()
+                } else {
+                    text("message deleted")
+                        .style(self.palette.system)
+                        .size(self.config.text_size)
+                        .width(Length::Fill)
+                        .into()
+                };
+                let mut name = text(author).size(self.config.text_size);
+                if let Some(c) = self.author_color(author, *color) {
+                    name = name.style(c);
+                }
+                let name = button(name)
+                    .padding(0)
+                    .style(iced::theme::Button::Text)
+                    .on_press(if self.menu == Some(*id) {
+                        Event::CloseMenu
+                    } else {
+                        Event::OpenMenu(*id)
+                    });
+                let name = tooltip(
+                    name,
+                    format!("Signed in with {}", protocol::auth_provider(*auth)),
+                    tooltip::Position::FollowCursor,
+                )
+                .style(iced::theme::Container::Box);
+                let stamp = text(timestamp.format(&self.config.timestamp))
+                    .style(self.palette.timestamp)
+                    .size(self.config.text_size);
+                let mut header = Row::new();
+                if let Some(badge) = self.palette.badge(*author_level) {
+                    header = header.push(
+                        tooltip(
+                            text(&badge.glyph)
+                                .style(badge.color)
+                                .size(self.config.text_size),
+                            &badge.name,
+                            tooltip::Position::FollowCursor,
+                        )
+                        .style(iced::theme::Container::Box),
+                    );
+                }
+                header = header.push(name);
+                if let Some(amount) = donation {
+                    header = header.push(
+                        text(format!(" donated {amount}"))
+                            .style(self.palette.highlight)
+                            .size(self.config.text_size),
+                    );
+                }
+                let mut body = row![content];
+                if moderator && !deleted {
+                    let author = author.clone();
+                    let author_id = *author_id;
+                    let action = |label, action| {
+                        button(text(label).size(self.config.text_size))
+                            .on_press(Event::Moderate(action))
+                            .padding(2)
+                    };
+                    body = body
+                        .push(action("Delete", Action::Delete { id: *id }))
+                        .push(action(
+                            "Purge",
+                            Action::Purge {
+                                author: author.clone(),
+                                author_id,
+                            },
+                        ))
+                        .push(action(
+                            "Timeout",
+                            Action::Timeout {
+                                author: author.clone(),
+                                author_id,
+                            },
+                        ))
+                        .push(action("Ban", Action::Ban { author, author_id }))
+                        .spacing(4);
+                }
+                let line: Element<'_, Event> = match self.config.layout {
+                    Layout::Compact => row![
+                        container(stamp).width(self.column_width(self.timestamp_width())),
+                        self.view_name_column(header, *continued),
+                        body
+                    ]
+                    .spacing(8)
+                    .into(),
+                    Layout::Cozy if *continued => container(body).padding([0, 0, 0, 16]).into(),
+                    Layout::Cozy => column![
+                        row![header, stamp].spacing(8),
+                        container(body).padding([0, 0, 0, 16])
+                    ]
+                    .into(),
+                    Layout::Hover => tooltip(
+                        row![self.view_name_column(header, *continued), body].spacing(8),
+                        timestamp.format(&self.config.timestamp).to_string(),
+                        tooltip::Position::FollowCursor,
+                    )
+                    .style(iced::theme::Container::Box)
+                    .into(),
+                };
+                let mut entry = column![line];
+                if self.menu == Some(*id) {
+                    let user = Ignored {
+                        id: Some(*author_id),
+                        name: author.clone(),
+                    };
+                    let action = |label: String, event| {
+                        button(text(label).size(self.config.text_size))
+                            .on_press(event)
+                            .padding(2)
+                    };
+                    let mut message_actions = row![
+                        action(String::from("Copy text"), Event::Copy(*id, Clip::Text)),
+                        action(
+                            String::from("Copy with author and time"),
+                            Event::Copy(*id, Clip::Line)
+                        ),
+                        action(String::from("Copy ID"), Event::Copy(*id, Clip::Id)),
+                        action(String::from("Reply"), Event::ReplyTo(*id)),
+                    ]
+                    .spacing(4);
+                    if moderator && !deleted {
+                        message_actions = message_actions.push(action(
+                            String::from("Delete"),
+                            Event::Moderate(Action::Delete { id: *id }),
+                        ));
+                    }
+                    entry = entry.push(message_actions).push(
+                        row![
+                            action(
+                                format!("Mention {author}"),
+                                Event::MentionUser(author.clone())
+                            ),
+                            action(
+                                format!("Only {author}'s messages"),
+                                Event::Filter(Some(author.clone()))
+                            ),
+                            action(
+                                format!("Ignore {author}"),
+                                Event::Ignore(user.clone(), None)
+                            ),
+                            action(
+                                format!(
+                                    "Mute for {} minutes",
+                                    self.config.mute_duration.div_ceil(60)
+                                ),
+                                Event::Ignore(user, Some(self.config.mute_duration))
+                            ),
+                            action(String::from("Close"), Event::CloseMenu),
+                        ]
+                        .spacing(4),
+                    );
+                }
+                let entry = ContextArea::new(entry).on_menu(if self.menu == Some(*id) {
+                    Event::CloseMenu
+                } else {
+                    Event::OpenMenu(*id)
+                });
+                let entry = if self.selected == Some(*id) {
+                    container(entry)
+                        .width(Length::Fill)
+                        .style(theme::tint(self.palette.highlight))
+                        .into()
+                } else if *mention {
+                    container(entry)
+                        .style(theme::tint(self.palette.mention))
+                        .into()
+                } else if donation.is_some() {
+                    container(entry).style(theme::donation()).into()
+                } else {
+                    entry.into()
+                };
+                self.view_day(*new_day, timestamp, entry)
+            }
+            Message::System(content) => self.view_markup(content, Some(self.palette.system)),
+        }
+    }
+
+    /// Puts the name part of a message in its column, leaving the column
+    /// empty when the message carries on from the one before.
+    fn view_name_column<'a>(&self, header: Row<'a, Event>, continued: bool) -> Element<'a, Event> {
+        container(if continued { Row::new() } else { header })
+            .width(self.column_width(NAME_COLUMN))
+            .align_x(alignment::Horizontal::Right)
+            .into()
+    }
+
+    /// Puts a line with the date above `entry` when it starts a new day.
+    fn view_day<'a>(
+        &self,
+        new_day: bool,
+        timestamp: &DateTime<Local>,
+        entry: Element<'a, Event>,
+    ) -> Element<'a, Event> {
+        if !new_day {
+            return entry;
+        }
+        column![
+            text(format!("──── {} ────", timestamp.format("%A %-d %B %Y")))
+                .style(self.palette.timestamp)
+                .size(self.config.text_size)
+                .width(Length::Fill)
+                .horizontal_alignment(alignment::Horizontal::Center),
+            entry
+        ]
+        .into()
+    }
+
+    /// Returns how wide `chars` characters are, roughly.
+    fn column_width(&self, chars: usize) -> Length {
+        Length::Units(((chars + 1) as f32 * self.config.text_size as f32 * 0.5) as u16)
+    }
+
+    /// Returns how many characters a timestamp takes in the configured
+    /// format.
+    fn timestamp_width(&self) -> usize {
+        Local::now()
+            .format(&self.config.timestamp)
+            .to_string()
+            .chars()
+            .count()
+    }
+
+    /// Lays out formatted text. iced can only draw one font, so emphasis is
+    /// shown with color and code gets a box behind it.
+    fn view_markup(&self, lines: &[Line], color: Option<Color>) -> Element<'_, Event> {
+        let size = self.config.text_size;
+        let span = |span: &Span| -> Element<'_, Event> {
+            let mut widget = text(&span.text).size(size);
+            if let Some(url) = &span.link {
+                return tooltip(
+                    button(widget.style(self.palette.highlight))
+                        .style(iced::theme::Button::Text)
+                        .padding(0)
+                        .on_press(Event::OpenLink(url.clone())),
+                    url,
+                    tooltip::Position::FollowCursor,
+                )
+                .style(iced::theme::Container::Box)
+                .into();
+            }
+            if span.bold {
+                widget = widget.style(self.palette.highlight);
+            } else if span.italic {
+                widget = widget.style(self.palette.timestamp);
+            } else if let Some(color) = color {
+                widget = widget.style(color);
+            }
+            if span.code {
+                container(widget).style(iced::theme::Container::Box).into()
+            } else {
+                widget.into()
+            }
+        };
+        // Plain text gets to wrap, which a row of spans can't.
+        if let [line] = lines {
+            if let [only] = &line[..] {
+                if only.link.is_none() && !only.code {
+                    let mut widget = text(&only.text).size(size).width(Length::Fill);
+                    if let Some(color) = color {
+                        widget = widget.style(color);
+                    }
+                    return widget.into();
+                }
+            }
+        }
+        Column::with_children(
+            lines
+                .iter()
+                .map(|line| Row::with_children(line.iter().map(span).collect()).into())
+                .collect(),
+        )
+        .width(Length::Fill)
+        .into()
+    }
+
+    /// Brings the log in line with the ignore list after it changed.
+    /// Messages that were hidden are gone for good, but collapsed ones come
+    /// back when their author is unignored.
+    fn apply_ignores(&mut self) {
+        let active: Vec<Ignored> = self.ignores().cloned().collect();
+        let ignoring = |author_id: usize, author: &str| {
+            active.iter().any(|user| user.matches(author_id, author))
+        };
+        let columns = self.columns();
+        let layout = self.config.layout;
+        match self.config.ignored_mode {
+            IgnoredMode::Collapse => {
+                self.messages.update(
+                    |message| match message {
+                        Message::Normal {
+                            author,
+                            author_id,
+                            ignored,
+                            ..
+                        } => {
+                            let was = *ignored;
+                            *ignored = ignoring(*author_id, author);
+                            *ignored != was
+                        }
+                        _ => false,
+                    },
+                    |message| rows(message, columns, layout),
+                );
+            }
+            IgnoredMode::Hide => {
+                let gone = |message: &Message| matches!(message, Message::Normal { author, author_id, .. } if ignoring(*author_id, author));
+                if let Some(marker) = self.unread_marker {
+                    let removed = self
+                        .messages
+                        .iter()
+                        .take(marker)
+                        .filter(|m| gone(m))
+                        .count();
+                    self.unread_marker = Some(marker - removed);
+                }
+                self.messages.retain(|message| !gone(message));
+            }
+        }
+        self.mark_groups();
+        self.refresh_search();
+    }
+
+    /// Adds someone to the ignore list, or mutes them for `duration` seconds.
+    fn ignore(&mut self, user: Ignored, duration: Option<u64>) {
+        if self.ignores().any(|ignored| ignored == &user) {
+            self.log(Message::system(format!("{} is already ignored", user.name)));
+            return;
+        }
+        match duration {
+            Some(seconds) => {
+                // The mute length can come from the config, which nothing
+                // stops from being absurd.
+                let Some(until) = Instant::now().checked_add(Duration::from_secs(seconds)) else {
+                    self.log(Message::system(format!(
+                        "Can't mute {} for that long",
+                        user.name
+                    )));
+                    return;
+                };
+                self.log(Message::system(format!(
+                    "Muted {} for {} minutes",
+                    user.name,
+                    seconds.div_ceil(60)
+                )));
+                self.muted.push((user, until));
+            }
+            None => {
+                self.log(Message::system(format!("Ignoring {user}")));
+                self.config.ignored.push(user);
+                self.save_config();
+            }
+        }
+        self.apply_ignores();
+    }
+
+    /// Returns everyone who is ignored or muted right now.
+    fn ignores(&self) -> impl Iterator<Item = &Ignored> {
+        self.config
+            .ignored
+            .iter()
+            .chain(self.muted.iter().map(|(user, _)| user))
+    }
+
+    fn is_ignored(&self, author_id: usize, author: &str) -> bool {
+        self.ignores().any(|user| user.matches(author_id, author))
+    }
+
+    /// Carries out a slash command.
+    fn run_command(&mut self, command: commands::Command) -> Command<Event> {
+        match command {
+            commands::Command::Clear => {
+                self.messages.take();
+                self.menu = None;
+                self.unread = 0;
+                self.unread_marker = None;
+                self.refresh_search();
+            }
+            commands::Command::Color(color) => match self.username.clone() {
+                Some(name) => self.set_user_conf(name, color),
+                None => self.log(Message::system("The server hasn't told us your name yet")),
+            },
+            commands::Command::Export(format, range) => self.export(format, range),
+            commands::Command::Help(topic) => match self.commands.help(topic.as_deref()) {
+                Ok(lines) => {
+                    for line in lines {
+                        self.log(Message::system(line));
+                    }
+                }
+                Err(e) => self.log(Message::system(e)),
+            },
+            commands::Command::Ignore { name: None, .. } => {
+                let list: Vec<String> = self.ignores().map(ToString::to_string).collect();
+                self.log(Message::system(if list.is_empty() {
+                    String::from("Nobody is ignored")
+                } else {
+                    format!("Ignoring {}", list.join(", "))
+                }));
+            }
+            commands::Command::Ignore {
+                name: Some(name),
+                seconds,
+            } => {
+                let user = self.lookup(&name);
+                self.ignore(user, seconds);
+            }
+            commands::Command::Me(text) => {
+                self.send(format!("*{text}*"), None);
+            }
+            commands::Command::Nick(name) => match self.user_color.clone() {
+                Some(color) => self.set_user_conf(name, color),
+                None => self.log(Message::system("The server hasn't told us your color yet")),
+            },
+            commands::Command::Quit => return iced::window::close(),
+            commands::Command::Reconnect => {
+                self.reconnects += 1;
+                self.socket = SocketState::Disconnected;
+                self.users.clear();
+                self.log(Message::system(format!(
+                    "Reconnecting to {}",
+                    self.config.server()
+                )));
+            }
+            commands::Command::Reply { id, text } => {
+                self.send(text, Some(id));
+            }
+            commands::Command::Script { args, name, script } => {
+                match commands::run_script(&self.scripts[script], &name, &args) {
+                    Ok(Some(text)) => {
+                        self.send(text, None);
+                    }
+                    Ok(None) => {}
+                    Err(e) => self.log(Message::system(format!("/{name} failed: {e}"))),
+                }
+            }
+            commands::Command::Unignore(name) => self.unignore(&name),
+        }
+        Command::none()
+    }
+
+    /// Sends a chat message, returning whether there was a connection to send
+    /// it over.
+    fn send(&mut self, text: String, reply: Option<usize>) -> bool {
+        match (&mut self.socket, &self.auth) {
+            (SocketState::Connected(connection), Some(auth)) => {
+                match connection.try_send(OutboundMessage::message(auth, text, reply)) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        self.log(Message::system(format!("Failed to send: {e}")));
+                        false
+                    }
+                }
+            }
+            _ => {
+                self.log(Message::system("Not connected, nothing was sent"));
+                false
+            }
+        }
+    }
+
+    /// Asks the server to change our name and color. It answers with a status.
+    fn set_user_conf(&mut self, name: String, color: String) {
+        match (&mut self.socket, &self.auth) {
+            (SocketState::Connected(connection), Some(auth)) => {
+                if let Err(e) =
+                    connection.try_send(OutboundMessage::set_user_conf(auth, name, color))
+                {
+                    self.log(Message::system(format!("Failed to send: {e}")));
+                }
+            }
+            _ => self.log(Message::system("Not connected, nothing was changed")),
+        }
+    }
+
+    /// Explains what the server said about our account.
+    fn status(&mut self, status: &UserStatus) -> Command<Event> {
+        let explanation = match status {
+            UserStatus::Authenticated => return Command::none(),
+            UserStatus::Banned => "You're banned from this server",
+            UserStatus::NameExists => "Somebody already has that name",
+            UserStatus::NameInvalid => "That name isn't allowed",
+            UserStatus::NameLength => "That name is too long or too short",
+            UserStatus::NameTimeout => "You changed your name too recently, try again later",
+            UserStatus::Rename => "Pick a name with /nick name",
+            UserStatus::SetUserConf => {
+                // Ask again so the title and highlights pick up the change.
+                if let (SocketState::Connected(connection), Some(auth)) =
+                    (&mut self.socket, &self.auth)
+                {
+                    if let Err(e) = connection.try_send(OutboundMessage::get_user_conf(auth)) {
+                        self.log(Message::system(format!("Failed to send: {e}")));
+                    }
+                }
+                "Your name and color were changed"
+            }
+            UserStatus::Unauthenticated => "The server didn't accept your token",
+        };
+        self.push(Message::system(explanation))
+    }
+
+    /// Puts the selected completion into the message box.
+    fn accept_completion(&mut self) -> Command<Event> {
+        if let Some(completion) = self.completion.take() {
+            completion.apply(&mut self.composer.input);
+            self.save_draft();
+        }
+        Command::batch([
+            text_input::focus(MESSAGE_INPUT.clone()),
+            text_input::move_cursor_to_end(MESSAGE_INPUT.clone()),
+        ])
+    }
+
+    /// Looks for completions of the word at the end of the message box.
+    fn complete(&mut self, explicit: bool) {
+        let commands = self.commands.names();
+        self.completion = Completion::new(
+            &self.composer.input,
+            || self.recent_names(),
+            &commands,
+            &self.config.emotes,
+            explicit,
+        );
+    }
+
+    /// Lists the people worth completing, whoever spoke most recently first
+    /// and then everyone else who's here.
+    fn recent_names(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        let authors = self
+            .messages
+            .iter()
+            .rev()
+            .filter_map(|message| match message {
+                Message::Normal { author, .. } => Some(author),
+                _ => None,
+            });
+        let present = self.users.sorted().into_iter().map(|user| &user.name);
+        for name in authors.chain(present) {
+            if self.username.as_ref() != Some(name) && seen.insert(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// Keeps what's in the message box as the current server's draft.
+    fn save_draft(&mut self) {
+        let server = self.config.server().clone();
+        self.drafts.set(&server, self.composer.text());
+    }
+
+    /// Hands out an ID for a new group of joins and parts.
+    fn next_presence_id(&mut self) -> usize {
+        self.presence_id += 1;
+        self.presence_id
+    }
+
+    /// Remembers everyone we mentioned in a message of our own.
+    fn note_mentions(&mut self, content: String) {
+        let now = Instant::now();
+        for word in content.split_whitespace() {
+            let name = word
+                .trim_start_matches('@')
+                .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+            if self.users.get(name).is_some() {
+                self.contacts.insert(name.to_string(), now);
+            }
+        }
+    }
+
+    /// Groups, splits up or drops the joins and parts in `messages` to match
+    /// the presence setting.
+    fn group_presence(&mut self, messages: Vec<Message>) -> Vec<Message> {
+        let mut entries = Vec::new();
+        for message in messages {
+            let events = match message {
+                Message::Presence { events, .. } => events,
+                message @ (Message::Join(_) | Message::Leave(_)) => vec![message],
+                message => {
+                    entries.push(message);
+                    continue;
+                }
+            };
+            match self.config.presence {
+                PresenceMode::All => entries.extend(events),
+                PresenceMode::Aggregate => match entries.last_mut() {
+                    Some(Message::Presence { events: group, .. }) => group.extend(events),
+                    _ => entries.push(Message::Presence {
+                        events,
+                        expanded: false,
+                        id: self.next_presence_id(),
+                    }),
+                },
+                PresenceMode::Hide => {}
+            }
+        }
+        entries
+    }
+
+    /// Groups or splits up the joins and parts already in the log to match
+    /// the presence setting.
+    fn regroup_presence(&mut self) {
+        let columns = self.columns();
+        let layout = self.config.layout;
+        let entries = self.messages.take();
+        let entries = self.group_presence(entries);
+        self.messages.prepend(
+            entries
+                .into_iter()
+                .map(|message| {
+                    let height = rows(&message, columns, layout);
+                    (message, height)
+                })
+                .collect(),
+        );
+        self.mark_groups();
+        // The positions in the log all changed.
+        self.unread_marker = None;
+        self.unread = 0;
+        self.refresh_search();
+    }
+
+    /// Works out again which messages share a header and where the days
+    /// start, after messages were added above or taken out of the log. Only
+    /// the messages whose marks changed are measured again.
+    fn mark_groups(&mut self) {
+        let window = self.config.group_window;
+        let mut grouping = Grouping::default();
+        let columns = self.columns();
+        let layout = self.config.layout;
+        self.messages.update(
+            |message| grouping.mark(message, window),
+            |message| rows(message, columns, layout),
+        );
+    }
+
+    /// Returns whether a join or part from `name` should be shown at all.
+    fn shows_presence(&self, name: &str) -> bool {
+        if self.config.presence_recent == 0 {
+            return true;
+        }
+        let recent = Duration::from_secs(self.config.presence_recent.saturating_mul(60));
+        self.contacts
+            .get(name)
+            .is_some_and(|when| when.elapsed() < recent)
+    }
+
+    /// Returns whether a message from `author` saying `content` mentions us.
+    /// Our own messages never do.
+    fn mentions_me(&self, author: &str, content: &str) -> bool {
+        self.username.as_deref() != Some(author) && self.highlighter.is_match(content)
+    }
+
+    /// Builds the mention rules again after our name or the configured ones
+    /// changed, and marks the log to match.
+    fn rebuild_highlighter(&mut self) {
+        let (highlighter, errors) = Highlighter::new(
+            self.username.as_deref(),
+            &self.config.highlights,
+            &self.config.highlight_patterns,
+        );
+        self.highlighter = highlighter;
+        for message in self.messages.iter_mut() {
+            if let Message::Normal {
+                author,
+                content,
+                mention,
+                ..
+            } = message
+            {
+                *mention =
+                    self.username.as_ref() != Some(author) && self.highlighter.is_match(content);
+            }
+        }
+        for error in errors {
+            self.log(Message::system(format!(
+                "Ignoring the highlight pattern {error}"
+            )));
+        }
+    }
+
+    /// Finds the ID of whoever last spoke as `name`, if they're in the log
+    /// or the user list.
+    fn lookup(&self, name: &str) -> Ignored {
+        let id = self
+            .messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                Message::Normal {
+                    author, author_id, ..
+                } if author == name => Some(*author_id),
+                _ => None,
+            })
+            .or_else(|| self.users.get(name)?.id);
+        Ignored {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    /// Saves the configuration after the client changed it, rather than the
+    /// settings screen.
+    fn save_config(&mut self) {
+        match self.config.save(CONFIG_PATH) {
+            Ok(()) => self.config_modified = config::modified(CONFIG_PATH),
+            Err(e) => self.log(Message::system(format!(
+                "Failed to save {CONFIG_PATH}: {e}"
+            ))),
+        }
+    }
+
+    /// Takes someone off the ignore list and ends any mute, matching on the
+    /// name they were ignored under.
+    fn unignore(&mut self, name: &str) {
+        let before = self.config.ignored.len() + self.muted.len();
+        let saved = self.config.ignored.len();
+        self.config.ignored.retain(|user| user.name != name);
+        self.muted.retain(|(user, _)| user.name != name);
+        if self.config.ignored.len() + self.muted.len() == before {
+            self.log(Message::system(format!("{name} isn't ignored")));
+            return;
+        }
+        if self.config.ignored.len() != saved {
+            self.save_config();
+        }
+        self.log(Message::system(format!("Stopped ignoring {name}")));
+        self.apply_ignores();
+    }
+
+    /// Finds everything in the log that matches the search again.
+    fn refresh_search(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.current = None;
+            search.matches = match &search.query {
+                Some(query) => self
+                    .messages
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, message)| found(query, message))
+                    .map(|(i, _)| i)
+                    .collect(),
+                None => Vec::new(),
+            };
+        }
+    }
+
+    /// Handles keys for picking messages: j and k move to the next newer or
+    /// older one, and with one picked, Enter opens its menu, c copies its
+    /// text (with Shift, its time and author too), i its ID, r replies, m
+    /// mentions the author, f shows only their messages, x ignores them and
+    /// Delete deletes it for moderators.
+    fn log_key(
+        &mut self,
+        key_code: keyboard::KeyCode,
+        modifiers: keyboard::Modifiers,
+    ) -> Command<Event> {
+        use keyboard::KeyCode;
+        let log_shown = self.settings.is_none()
+            && self.exporter.is_none()
+            && !self.leaderboard
+            && !self.mentions_open;
+        if !log_shown || modifiers.command() || modifiers.alt() {
+            return Command::none();
+        }
+        match key_code {
+            KeyCode::J => return self.select(true),
+            KeyCode::K => return self.select(false),
+            _ => {}
+        }
+        let Some(id) = self.selected else {
+            return Command::none();
+        };
+        let Some(Message::Normal {
+            author, author_id, ..
+        }) = self
+            .messages
+            .iter()
+            .find(|message| matches!(message, Message::Normal { id: found, .. } if *found == id))
+        else {
+            return Command::none();
+        };
+        let event = match key_code {
+            KeyCode::Enter if self.menu == Some(id) => Event::CloseMenu,
+            KeyCode::Enter => Event::OpenMenu(id),
+            KeyCode::C if modifiers.shift() => Event::Copy(id, Clip::Line),
+            KeyCode::C => Event::Copy(id, Clip::Text),
+            KeyCode::Delete => Event::Moderate(Action::Delete { id }),
+            KeyCode::F => Event::Filter(Some(author.clone())),
+            KeyCode::I => Event::Copy(id, Clip::Id),
+            KeyCode::M => Event::MentionUser(author.clone()),
+            KeyCode::R => Event::ReplyTo(id),
+            KeyCode::X => Event::Ignore(
+                Ignored {
+                    id: Some(*author_id),
+                    name: author.clone(),
+                },
+                None,
+            ),
+            _ => return Command::none(),
+        };
+        self.update(event)
+    }
+
+    /// Picks the next newer or older chat message, scrolling to it if it's
+    /// out of view. Going older with nothing picked starts from the newest.
+    fn select(&mut self, newer: bool) -> Command<Event> {
+        let pickable = |message: &Message| match message {
+            Message::Normal {
+                author,
+                ignored: false,
+                ..
+            } => self.filter.as_ref().is_none_or(|name| name == author),
+            _ => false,
+        };
+        let current = self.selected.and_then(|id| {
+            self.messages.iter().position(
+                |message| matches!(message, Message::Normal { id: found, .. } if *found == id),
+            )
+        });
+        let len = self.messages.len();
+        let next = match (current, newer) {
+            (Some(i), true) => (i + 1..len).find(|&j| self.messages.get(j).is_some_and(pickable)),
+            (Some(i), false) => (0..i)
+                .rev()
+                .find(|&j| self.messages.get(j).is_some_and(pickable)),
+            (None, true) => None,
+            (None, false) => (0..len)
+                .rev()
+                .find(|&j| self.messages.get(j).is_some_and(pickable)),
+        };
+        let Some(i) = next else {
+            return Command::none();
+        };
+        if let Some(Message::Normal { id, .. }) = self.messages.get(i) {
+            self.selected = Some(*id);
+        }
+        let view = self.viewport.1 as f32 / self.line_height();
+        let first = self.scroll * (self.messages.height() as f32 - view).max(0.0);
+        let top = self.messages.top(i) as f32;
+        // The window also holds the toolbar and the message box, so leave
+        // some room at the bottom.
+        if top >= first && top + 4.0 <= first + view {
+            return Command::none();
+        }
+        self.scroll_to(i)
+    }
+
+    /// Scrolls so the entry at `i` is a little way down from the top of the
+    /// view.
+    fn scroll_to(&mut self, i: usize) -> Command<Event> {
+        let line = self.line_height();
+        let view = self.viewport.1 as f32 / line;
+        let scrollable = self.messages.height() as f32 - view;
+        if scrollable <= 0.0 {
+            return Command::none();
+        }
+        let top = (self.messages.top(i) as f32 - view / 3.0).max(0.0);
+        self.scroll = (top / scrollable).min(1.0);
+        self.following = self.scroll >= 0.999;
+        scrollable::snap_to(
+            MESSAGE_LOG.clone(),
+            scrollable::RelativeOffset {
+                x: 0.0,
+                y: self.scroll,
+            },
+        )
+    }
+
+    fn update_search(&mut self, event: search::Event) -> Command<Event> {
+        let Some(search) = &mut self.search else {
+            return Command::none();
+        };
+        match event {
+            search::Event::CaseChange(on) => search.case_sensitive = on,
+            search::Event::Close => {
+                self.search = None;
+                return Command::none();
+            }
+            search::Event::Next | search::Event::Previous => {
+                let forward = matches!(event, search::Event::Next);
+                return match search.step(forward) {
+                    Some(i) => self.scroll_to(i),
+                    None => Command::none(),
+                };
+            }
+            search::Event::QueryChange(s) => search.text = s,
+            search::Event::RegexChange(on) => search.regex = on,
+            search::Event::SearchHistory => {
+                let Some(query) = &search.query else {
+                    return Command::none();
+                };
+                search.error = None;
+                let Some(history) = &self.history else {
+                    search.error = Some(String::from("Chat history is turned off"));
+                    return Command::none();
+                };
+                let text = search.text.clone();
+                return Command::perform(history.search(query.clone(), SEARCH_LIMIT), |found| {
+                    Event::Search(search::Event::HistoryFound(text, found))
+                });
+            }
+            // The query changed while the search ran.
+            search::Event::HistoryFound(text, _) if text != search.text => {
+                return Command::none();
+            }
+            search::Event::HistoryFound(_, found) => {
+                match found {
+                    Ok(records) if records.is_empty() => {
+                        search.error = Some(String::from("No older matches in history"));
+                    }
+                    Ok(records) => search.history = records,
+                    Err(e) => search.error = Some(format!("Failed to search history: {e}")),
+                }
+                return Command::none();
+            }
+        }
+        search.compile();
+        self.refresh_search();
+        Command::none()
+    }
+
+    /// Writes the part of the log in `range` to a file and says where it
+    /// went.
+    fn export(&mut self, format: export::Format, range: export::Range) {
+        let result = export::write(
+            self.messages.iter(),
+            format,
+            range,
+            self.auth_level >= protocol::AUTH_MODERATOR,
+            &self.config.timestamp,
+            &self.palette,
+            |author, color| self.author_color(author, color),
+        );
+        self.log(Message::system(match result {
+            Ok(path) => format!("Exported the log to {}", path.display()),
+            Err(e) => format!("Failed to export the log: {e}"),
+        }));
+    }
+
+    /// Sends a moderator action to the server, noting it in the moderation
+    /// log once it's on its way.
+    fn moderate(&mut self, action: Action) {
+        if self.auth_level < protocol::AUTH_MODERATOR {
+            return;
+        }
+        let (SocketState::Connected(connection), Some(auth)) = (&mut self.socket, &self.auth)
+        else {
+            self.log(Message::system(String::from(
+                "Not connected, so nothing was done",
+            )));
+            return;
+        };
+        let payload = match &action {
+            Action::Ban { author_id, .. } => OutboundMessage::ban(auth, *author_id),
+            Action::Delete { id } => OutboundMessage::delete(auth, vec![*id]),
+            Action::Purge { author, author_id } => {
+                let since = Local::now() - chrono::Duration::minutes(PURGE_WINDOW);
+                let ids: Vec<usize> = self
+                    .messages
+                    .iter()
+                    .filter_map(|message| match message {
+                        Message::Normal {
+                            author_id: sender,
+                            deleted: false,
+                            id,
+                            timestamp,
+                            ..
+                        } if sender == author_id && *timestamp >= since => Some(*id),
+                        _ => None,
+                    })
+                    .collect();
+                if ids.is_empty() {
+                    self.log(Message::system(format!(
+                        "{author} hasn't said anything in the last {PURGE_WINDOW} minutes"
+                    )));
+                    return;
+                }
+                OutboundMessage::delete(auth, ids)
+            }
+            Action::Timeout { author_id, .. } => {
+                OutboundMessage::timeout(auth, *author_id, self.config.timeout_duration)
+            }
+        };
+        if let Err(e) = connection.try_send(payload) {
+            self.log(Message::system(format!("Failed to send: {e}")));
+            return;
+        }
+        self.record_moderation(action.to_string());
+    }
+
+    /// Adds a line to the moderation log, complaining in the chat if that
+    /// fails.
+    fn record_moderation(&mut self, entry: String) {
+        if let Err(e) = moderation::record(&entry) {
+            self.log(Message::system(format!(
+                "Failed to write to the moderation log: {e}"
+            )));
+        }
+    }
+
+    /// Picks the color to draw an author's name in. Local overrides are used
+    /// as they are, while the color the server sent is made readable against
+    /// the current background first.
+    fn author_color(&self, author: &str, color: Option<Color>) -> Option<Color> {
+        if let Some(color) = self
+            .config
+            .colors
+            .get(author)
+            .and_then(|c| theme::parse_color(c))
+        {
+            return Some(color);
+        }
+        color.map(|c| theme::readable(c, self.palette.background))
+    }
+
+    /// Swaps in a freshly loaded configuration, only touching the connection
+    /// and the scripts when the settings they depend on have changed.
+    fn apply_config(&mut self, config: Configuration) -> Command<Event> {
+        if config == self.config {
+            return Command::none();
+        }
+        if config.scripts() != self.config.scripts() {
+            match load_scripts(config.scripts()) {
+                Ok(scripts) => {
+                    let (commands, errors) = Registry::new(&scripts);
+                    for e in errors {
+                        self.log(Message::system(e));
+                    }
+                    self.commands = commands;
+                    self.scripts = scripts;
+                }
+                Err(e) => self.log(Message::system(e)),
+            }
+        }
+        if config.theme != self.config.theme {
+            match Palette::load(&config.theme) {
+                Ok(palette) => self.palette = palette,
+                Err(e) => self.log(Message::system(format!(
+                    "Failed to load the {} theme: {e}",
+                    config.theme
+                ))),
+            }
+        }
+        if config.scrollback != self.config.scrollback {
+            let dropped = self.messages.set_limit(config.scrollback);
+            self.unread_marker = self.unread_marker.and_then(|i| i.checked_sub(dropped));
+            self.refresh_search();
+        }
+        let rehighlight = config.highlights != self.config.highlights
+            || config.highlight_patterns != self.config.highlight_patterns;
+        let regroup = config.presence != self.config.presence;
+        let reignore = config.ignored != self.config.ignored
+            || config.ignored_mode != self.config.ignored_mode;
+        let remeasure = config.text_size != self.config.text_size
+            || config.layout != self.config.layout
+            || config.group_window != self.config.group_window;
+        let reconnect = config.server() != self.config.server();
+        let token_changed = !config.same_token_source(&self.config);
+        let reopen = reconnect || config.history != self.config.history;
+        self.config = config;
+        if reconnect {
+            // Each server has its own draft.
+            self.composer
+                .set_text(self.drafts.get(self.config.server()));
+        }
+        if reopen {
+            self.open_history();
+        }
+        if remeasure {
+            self.mark_groups();
+            let columns = self.columns();
+            let layout = self.config.layout;
+            self.messages
+                .refresh(|message| rows(message, columns, layout));
+        }
+        if reignore {
+            self.apply_ignores();
+        }
+        if rehighlight {
+            self.rebuild_highlighter();
+        }
+        if regroup {
+            self.regroup_presence();
+        }
+        if reconnect {
+            // The socket subscription is keyed on the server, so it reconnects
+            // on its own. We just have to forget the old one.
+            self.socket = SocketState::Disconnected;
+        }
+        if token_changed {
+            self.resolve_auth()
+        } else {
+            Command::none()
+        }
+    }
+
+    /// Looks up the token again in the background.
+    fn resolve_auth(&mut self) -> Command<Event> {
+        self.token_lookups += 1;
+        let lookup = self.token_lookups;
+        Command::perform(
+            token::resolve(self.config.clone(), self.passphrase.clone()),
+            move |result| Event::TokenResolved(lookup, result),
+        )
+    }
+
+    /// Switches to the token that was looked up, asking for the passphrase if
+    /// it's stored encrypted and we don't know it yet.
+    fn token_resolved(&mut self, result: Result<MessageAuth, Arc<TokenError>>) {
+        let auth = self.auth.clone();
+        match result.as_ref().map_err(Arc::as_ref) {
+            Ok(auth) => {
+                self.auth = Some(auth.clone());
+                self.unlock = None;
+            }
+            Err(TokenError::Locked) => {
+                self.auth = None;
+                self.unlock = Some(String::new());
+            }
+            Err(e) => {
+                self.auth = None;
+                if let TokenError::Store = e {
+                    // Most likely a typo in the passphrase, so ask again.
+                    self.passphrase = None;
+                    self.unlock = Some(String::new());
+                }
+                self.log(Message::system(format!("Failed to get the token: {e}")));
+            }
+        }
+        if auth != self.auth {
+            // The socket subscription is keyed on the token, so it reconnects
+            // on its own. We just have to forget the old one.
+            self.socket = SocketState::Disconnected;
+        }
+    }
+}
+
+/// Sums up a group of joins and parts, like "+3 joined, -2 left (alice, bob,
+/// …)".
+fn presence_summary(events: &[Message]) -> String {
+    match events {
+        [Message::Join(name)] => return format!("+{name}"),
+        [Message::Leave(name)] => return format!("-{name}"),
+        _ => {}
+    }
+    let mut joined = 0;
+    let mut left = 0;
+    let mut names = Vec::new();
+    for event in events {
+        match event {
+            Message::Join(name) => {
+                joined += 1;
+                names.push(name.as_str());
+            }
+            Message::Leave(name) => {
+                left += 1;
+                names.push(name.as_str());
+            }
+            _ => {}
+        }
+    }
+    let mut counts = Vec::new();
+    if joined > 0 {
+        counts.push(format!("+{joined} joined"));
+    }
+    if left > 0 {
+        counts.push(format!("-{left} left"));
+    }
+    names.dedup();
+    let mut shown = names.iter().take(3).copied().collect::<Vec<_>>().join(", ");
+    if names.len() > 3 {
+        shown.push_str(", …");
+    }
+    format!("{} ({shown})", counts.join(", "))
+}
+
+/// Returns whether `message` matches `query`, by its content or its author.
+fn found(query: &search::Query, message: &Message) -> bool {
+    match message {
+        Message::Normal {
+            author, content, ..
+        } => query.is_match(author) || query.is_match(content),
+        Message::System(lines) => query.is_match(&markup::plain(lines)),
+        Message::Join(_) | Message::Leave(_) | Message::Presence { .. } => false,
+    }
+}
+
+/// Applies a deletion from the server to the log. Depending on `mode` the
+/// messages either disappear or stay behind as tombstones.
+fn delete_messages(
+    log: &mut Scrollback<Message>,
+    victims: &[usize],
+    mode: DeletedMode,
+    columns: usize,
+    layout: Layout,
+) {
+    match mode {
+        DeletedMode::Hide => log.retain(|message| match message {
+            Message::Normal { id, .. } => !victims.contains(id),
+            _ => true,
+        }),
+        DeletedMode::Placeholder => log.update(
+            |message| match message {
+                Message::Normal { id, deleted, .. } if !*deleted && victims.contains(id) => {
+                    *deleted = true;
+                    true
+                }
+                _ => false,
+            },
+            |message| rows(message, columns, layout),
+        ),
+    }
+}
+
+/// Estimates how many lines `message` takes up in `layout` when `columns`
+/// characters fit on a line.
+fn rows(message: &Message, columns: usize, layout: Layout) -> u32 {
+    let wrapped = |length: usize| length.div_ceil(columns).max(1) as u32;
+    match message {
+        Message::Join(_) | Message::Leave(_) => 1,
+        Message::Presence {
+            events,
+            expanded: true,
+            ..
+        } => events.len() as u32 + 1,
+        Message::Presence { events, .. } => wrapped(presence_summary(events).len()),
+        Message::Normal {
+            continued,
+            deleted,
+            ignored,
+            new_day,
+            rich,
+            ..
+        } => {
+            // Cozy puts the name and time on a line of their own.
+            let header = (layout == Layout::Cozy && !continued && !ignored) as u32;
+            // The compact layouts put the columns before the first line. The
+            // timestamp is guessed to be about as wide as the default one.
+            let prefix = match layout {
+                Layout::Compact => NAME_COLUMN + 12,
+                Layout::Cozy => 2,
+                Layout::Hover => NAME_COLUMN,
+            };
+            let body = if *ignored {
+                1
+            } else if *deleted {
+                wrapped(prefix + "message deleted".len())
+            } else {
+                rich.iter()
+                    .enumerate()
+                    .map(|(i, line)| {
+                        let prefix = if i == 0 { prefix } else { 0 };
+                        wrapped(prefix + line.iter().map(|span| span.text.len()).sum::<usize>())
+                    })
+                    .sum()
+            };
+            *new_day as u32 + header + body
+        }
+        Message::System(lines) => lines
+            .iter()
+            .map(|line| wrapped(line.iter().map(|span| span.text.len()).sum()))
+            .sum(),
+    }
+}
+
+/// Keeps track of the last message seen, to tell where groups of messages
+/// from one person and days start.
+#[derive(Default)]
+struct Grouping {
+    day: Option<NaiveDate>,
+    /// Who sent the message before, and when, if it can be carried on from.
+    last: Option<(String, DateTime<Local>)>,
+}
+
+impl Grouping {
+    /// Picks up where `messages` leave off, looking back only as far as the
+    /// last chat message.
+    fn after<'a, I: DoubleEndedIterator<Item = &'a Message>>(messages: I) -> Self {
+        let mut grouping = Self::default();
+        for (i, message) in messages.rev().enumerate() {
+            if let Message::Normal {
+                author,
+                donation,
+                ignored,
+                timestamp,
+                ..
+            } = message
+            {
+                grouping.day = Some(timestamp.date_naive());
+                if i == 0 && !ignored && donation.is_none() {
+                    grouping.last = Some((author.clone(), *timestamp));
+                }
+                break;
+            }
+        }
+        grouping
+    }
+
+    /// Marks whether `message` starts a new day and whether it carries on
+    /// from the one before, when they're less than `window` seconds apart.
+    /// Returns whether either mark changed.
+    fn mark(&mut self, message: &mut Message, window: u64) -> bool {
+        let Message::Normal {
+            author,
+            continued,
+            donation,
+            ignored,
+            new_day,
+            timestamp,
+            ..
+        } = message
+        else {
+            self.last = None;
+            return false;
+        };
+        let before = (*continued, *new_day);
+        let day = timestamp.date_naive();
+        *new_day = self.day.is_some_and(|last| last != day);
+        self.day = Some(day);
+        // Donations always get a header, since that's where the amount goes.
+        let groups = !*ignored && donation.is_none();
+        *continued = groups
+            && !*new_day
+            && self.last.as_ref().is_some_and(|(last, time)| {
+                last == author && (*timestamp - *time).num_seconds() < window as i64
+            });
+        self.last = groups.then(|| (author.clone(), *timestamp));
+        before != (*continued, *new_day)
+    }
+}
+
+/// Builds empty space `height` pixels tall. `Length::Units` only goes up to
+/// `u16::MAX`, which a long log easily outgrows, so it comes in pieces.
+fn spacer<'a>(height: f32) -> Column<'a, Event> {
+    let mut spacer = Column::new();
+    let mut left = height.max(0.0) as u32;
+    while left > 0 {
+        let piece = left.min(u16::MAX as u32);
+        spacer = spacer.push(Space::with_height(Length::Units(piece as u16)));
+        left -= piece;
+    }
+    spacer
+}
+
+/// Produces `event` only if the text input `id` has focus. Text inputs let Up
+/// and Down through, so that's the only way to tell who they were meant for.
+fn if_focused(id: text_input::Id, event: Event) -> Command<Event> {
+    struct Check {
+        event: Event,
+        focused: bool,
+        target: widget::Id,
+    }
+
+    impl Operation<Event> for Check {
+        fn container(
+            &mut self,
+            _id: Option<&widget::Id>,
+            operate_on_children: &mut dyn FnMut(&mut dyn Operation<Event>),
+        ) {
+            operate_on_children(self)
+        }
+
+        fn focusable(&mut self, state: &mut dyn Focusable, id: Option<&widget::Id>) {
+            if id == Some(&self.target) && state.is_focused() {
+                self.focused = true;
+            }
+        }
+
+        fn finish(&self) -> Outcome<Event> {
+            if self.focused {
+                Outcome::Some(self.event.clone())
+            } else {
+                Outcome::None
+            }
+        }
+    }
+
+    Command::widget(Check {
+        event,
+        focused: false,
+        target: id.into(),
+    })
+}
+
+fn load_scripts(paths: &[String]) -> Result<Vec<Interpreter>, String> {
+    paths
+        .iter()
+        .map(|path| {
+            let interp = Interpreter::new();
+            match interp.run_file(Path::new(path)) {
+                Ok(()) => Ok(interp),
+                Err(e) => Err(format!(
+                    "Failed to load {path}: {}",
+                    interp.format_error(&e)
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Strikes through `content` with combining characters, since iced can't
+/// decorate text on its own.
+fn strike(content: &str) -> String {
+    content.chars().flat_map(|c| [c, '\u{0336}']).collect()
+}
+
+#[derive(Clone)]
+enum Message {
+    Join(String),
+    Leave(String),
+    Normal {
+        auth: usize,
+        author: String,
+        author_id: usize,
+        author_level: usize,
+        color: Option<Color>,
+        content: String,
+        /// Whether it carries on from the message before it, so it goes
+        /// under the same header.
+        continued: bool,
+        deleted: bool,
+        donation: Option<String>,
+        id: usize,
+        ignored: bool,
+        mention: bool,
+        /// Whether it's the first message of a new day.
+        new_day: bool,
+        reply: usize,
+        rich: Vec<Line>,
+        timestamp: DateTime<Local>,
+    },
+    /// A run of joins and parts shown as one line.
+    Presence {
+        events: Vec<Message>,
+        expanded: bool,
+        id: usize,
+    },
+    System(Vec<Line>),
+}
+
+impl Message {
+    /// Turns something the server sent into a line for the log, if it's the
+    /// kind of thing that goes in the log.
+    fn from_inbound(data: &InboundData) -> Option<Self> {
+        match data {
+            InboundData::Chat {
+                auth,
+                author,
+                author_color,
+                author_id,
+                author_level,
+                donate_value,
+                message,
+                id,
+                reply,
+                time,
+            } => {
+                let rich = markup::parse(message);
+                Some(Message::Normal {
+                    auth: *auth,
+                    author: author.clone(),
+                    author_id: *author_id,
+                    author_level: *author_level,
+                    color: theme::parse_color(author_color),
+                    content: markup::plain(&rich),
+                    deleted: false,
+                    donation: donations::donation(donate_value),
+                    id: *id,
+                    continued: false,
+                    ignored: false,
+                    mention: false,
+                    new_day: false,
+                    reply: *reply,
+                    rich,
+                    timestamp: Local.timestamp_millis_opt(*time as _).unwrap(),
+                })
+            }
+            InboundData::Join { name } => Some(Message::Join(name.clone())),
+            InboundData::Part { name } => Some(Message::Leave(name.clone())),
+            InboundData::ServerMsg { message } => Some(Message::System(markup::parse(message))),
+            _ => None,
+        }
+    }
+
+    /// A system message from the client itself, which is never treated as
+    /// markup.
+    fn system<S: Into<String>>(content: S) -> Self {
+        Message::System(vec![vec![Span {
+            text: content.into(),
+            ..Default::default()
+        }]])
+    }
+}
+
+enum SocketState {
+    Connected(socket::Connection),
+    Disconnected,
+}
+
+fn main() -> iced::Result {
+    ElmKC::run(Settings::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(id: usize) -> Message {
+        Message::Normal {
+            auth: 0,
+            author: format!("user{id}"),
+            author_id: id,
+            author_level: 0,
+            color: None,
+            content: format!("message {id}"),
+            continued: false,
+            deleted: false,
+            donation: None,
+            id,
+            ignored: false,
+            mention: false,
+            new_day: false,
+            reply: 0,
+            rich: markup::parse(&format!("message {id}")),
+            timestamp: Local::now(),
+        }
+    }
+
+    fn log(ids: &[usize]) -> Scrollback<Message> {
+        let mut log = Scrollback::new(0);
+        for &id in ids {
+            log.push(chat(id), 1);
+        }
+        log
+    }
+
+    fn ids(log: &Scrollback<Message>) -> Vec<usize> {
+        log.iter()
+            .filter_map(|message| match message {
+                Message::Normal { id, .. } => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hide_removes_only_the_victims() {
+        let mut messages = log(&[1, 2, 3, 4, 5, 6, 7]);
+        delete_messages(
+            &mut messages,
+            &[2, 5, 7],
+            DeletedMode::Hide,
+            80,
+            Layout::Compact,
+        );
+        assert_eq!(ids(&messages), [1, 3, 4, 6]);
+        assert_eq!(messages.height(), 4);
+    }
+
+    #[test]
+    fn hide_ignores_ids_not_in_the_log() {
+        let mut messages = log(&[1, 2, 3]);
+        delete_messages(
+            &mut messages,
+            &[9, 1, 3],
+            DeletedMode::Hide,
+            80,
+            Layout::Compact,
+        );
+        assert_eq!(ids(&messages), [2]);
+    }
+
+    #[test]
+    fn placeholder_marks_only_the_victims() {
+        let mut messages = log(&[1, 2, 3, 4, 5, 6, 7]);
+        delete_messages(
+            &mut messages,
+            &[7, 2, 5],
+            DeletedMode::Placeholder,
+            80,
+            Layout::Compact,
+        );
+        let deleted: Vec<usize> = messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Normal {
+                    id, deleted: true, ..
+                } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(deleted, [2, 5, 7]);
+        assert_eq!(ids(&messages), [1, 2, 3, 4, 5, 6, 7]);
+    }
 }
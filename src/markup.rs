@@ -0,0 +1,211 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! The small subset of HTML that ChatKC puts in messages. Anything outside of
+//! it is dropped and only its text is kept, so a message can't draw anything
+//! but plain text, emphasis, code and links.
+
+use std::{io, process::Command};
+
+/// A run of text with the same formatting.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    pub bold: bool,
+    pub code: bool,
+    pub italic: bool,
+    pub link: Option<String>,
+    pub text: String,
+}
+
+pub type Line = Vec<Span>;
+
+#[derive(Default)]
+struct State {
+    bold: usize,
+    code: usize,
+    italic: usize,
+    links: Vec<Option<String>>,
+}
+
+impl State {
+    fn span(&self, text: String) -> Span {
+        Span {
+            bold: self.bold > 0,
+            code: self.code > 0,
+            italic: self.italic > 0,
+            link: self.links.iter().rev().find_map(Clone::clone),
+            text,
+        }
+    }
+}
+
+/// Splits `html` into lines of formatted spans.
+pub fn parse(html: &str) -> Vec<Line> {
+    let mut lines = vec![Vec::new()];
+    let mut state = State::default();
+    let mut rest = html;
+    while !rest.is_empty() {
+        let (text, tag) = match rest.find('<') {
+            Some(start) => match rest[start..].find('>') {
+                Some(end) => (&rest[..start], Some(&rest[start + 1..start + end])),
+                None => (rest, None),
+            },
+            None => (rest, None),
+        };
+        if !text.is_empty() {
+            let decoded = html_escape::decode_html_entities(text).into_owned();
+            push_text(lines.last_mut().unwrap(), &state, decoded);
+        }
+        let Some(tag) = tag else {
+            break;
+        };
+        rest = &rest[text.len() + tag.len() + 2..];
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let counter = match name.as_str() {
+            "b" | "strong" => Some(&mut state.bold),
+            "code" => Some(&mut state.code),
+            "em" | "i" => Some(&mut state.italic),
+            _ => None,
+        };
+        match (name.as_str(), counter) {
+            ("br", _) => lines.push(Vec::new()),
+            (_, Some(counter)) if closing => *counter = counter.saturating_sub(1),
+            (_, Some(counter)) => *counter += 1,
+            ("a", None) if closing => {
+                state.links.pop();
+            }
+            ("a", None) => state.links.push(href(tag)),
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// Returns the text of `lines` without any formatting.
+pub fn plain(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|span| span.text.as_str())
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes `lines` back out as HTML, using only the tags `parse` understands.
+pub fn html(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|span| {
+                    let mut out = html_escape::encode_text(&span.text).into_owned();
+                    if span.code {
+                        out = format!("<code>{out}</code>");
+                    }
+                    if span.italic {
+                        out = format!("<i>{out}</i>");
+                    }
+                    if span.bold {
+                        out = format!("<b>{out}</b>");
+                    }
+                    if let Some(link) = &span.link {
+                        out = format!(
+                            "<a href=\"{}\">{out}</a>",
+                            html_escape::encode_double_quoted_attribute(link)
+                        );
+                    }
+                    out
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("<br>")
+}
+
+/// Opens `url` with whatever the desktop uses for links.
+pub fn open(url: &str) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut command = Command::new("open");
+    // Not `cmd /C start`, which would run anything after a `&` in the URL.
+    #[cfg(windows)]
+    let mut command = Command::new("explorer.exe");
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = Command::new("xdg-open");
+    command.arg(url).spawn().map(|_| ())
+}
+
+/// Pulls the target out of an anchor tag, as long as it's a web address.
+fn href(tag: &str) -> Option<String> {
+    let start = tag.find("href=")? + 5;
+    let value = &tag[start..];
+    let value = match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next()?,
+        _ => value.split_whitespace().next()?,
+    };
+    let value = html_escape::decode_html_entities(value).into_owned();
+    if is_web(&value) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn is_web(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Adds `text` to `line`, turning bare web addresses into links unless we're
+/// already inside a link or some code.
+fn push_text(line: &mut Line, state: &State, text: String) {
+    if state.code > 0 || state.links.iter().any(Option::is_some) {
+        line.push(state.span(text));
+        return;
+    }
+    let mut plain = String::new();
+    for word in text.split_inclusive(char::is_whitespace) {
+        let trimmed = word.trim_end();
+        let url = trimmed.trim_end_matches(['.', ',', ')', '!', '?', ';', ':']);
+        if is_web(url)
+            && url
+                .split_once("://")
+                .is_some_and(|(_, rest)| !rest.is_empty())
+        {
+            if !plain.is_empty() {
+                line.push(state.span(std::mem::take(&mut plain)));
+            }
+            let mut span = state.span(url.to_string());
+            span.link = Some(url.to_string());
+            line.push(span);
+            plain.push_str(&word[url.len()..]);
+        } else {
+            plain.push_str(word);
+        }
+    }
+    if !plain.is_empty() {
+        line.push(state.span(plain));
+    }
+}
@@ -0,0 +1,108 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Picking out messages that mention us, by our name or by the keywords and
+//! patterns in the configuration.
+
+use chrono::{DateTime, Local};
+use regex::{Regex, RegexBuilder};
+
+/// The most mentions the panel keeps.
+const LIMIT: usize = 500;
+
+#[derive(Default)]
+pub struct Highlighter {
+    patterns: Vec<Regex>,
+}
+
+impl Highlighter {
+    /// Builds the rules for `username` and the configured keywords, which
+    /// match whole words regardless of case, and patterns, which are regexes
+    /// used as they are. Patterns that don't compile are left out and
+    /// returned as errors.
+    pub fn new(
+        username: Option<&str>,
+        keywords: &[String],
+        patterns: &[String],
+    ) -> (Self, Vec<String>) {
+        let mut compiled = Vec::new();
+        let mut errors = Vec::new();
+        for keyword in username
+            .into_iter()
+            .chain(keywords.iter().map(String::as_str))
+        {
+            let keyword = keyword.trim();
+            if keyword.is_empty() {
+                continue;
+            }
+            let pattern = format!(r"(?:^|\W){}(?:\W|$)", regex::escape(keyword));
+            match RegexBuilder::new(&pattern).case_insensitive(true).build() {
+                Ok(regex) => compiled.push(regex),
+                Err(e) => errors.push(format!("\"{keyword}\": {e}")),
+            }
+        }
+        for pattern in patterns {
+            match Regex::new(pattern) {
+                Ok(regex) => compiled.push(regex),
+                Err(e) => errors.push(format!("\"{pattern}\": {e}")),
+            }
+        }
+        (Self { patterns: compiled }, errors)
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.is_match(text))
+    }
+}
+
+/// A message that mentioned us, kept for the mentions panel even after it
+/// leaves the log.
+pub struct Mention {
+    pub author: String,
+    pub content: String,
+    pub id: usize,
+    pub timestamp: DateTime<Local>,
+}
+
+#[derive(Default)]
+pub struct Mentions {
+    list: Vec<Mention>,
+    /// How many arrived since the panel was last opened.
+    unread: usize,
+}
+
+impl Mentions {
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Mention> {
+        self.list.iter()
+    }
+
+    /// Marks everything as seen.
+    pub fn read(&mut self) {
+        self.unread = 0;
+    }
+
+    pub fn record(&mut self, mention: Mention) {
+        if self.list.len() == LIMIT {
+            self.list.remove(0);
+        }
+        self.list.push(mention);
+        self.unread += 1;
+    }
+
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+}
@@ -0,0 +1,62 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+use chrono::Local;
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+};
+
+const LOG_PATH: &str = "moderation.log";
+
+/// Something a moderator can do to a message or to the person who sent it.
+#[derive(Clone, Debug)]
+pub enum Action {
+    Ban { author: String, author_id: usize },
+    Delete { id: usize },
+    Purge { author: String, author_id: usize },
+    Timeout { author: String, author_id: usize },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Ban { author, author_id } => write!(f, "banned {author} ({author_id})"),
+            Action::Delete { id } => write!(f, "deleted message {id}"),
+            Action::Purge { author, author_id } => {
+                write!(f, "deleted recent messages from {author} ({author_id})")
+            }
+            Action::Timeout { author, author_id } => {
+                write!(f, "timed out {author} ({author_id})")
+            }
+        }
+    }
+}
+
+/// Appends a timestamped line to the local moderation log.
+pub fn record<S: AsRef<str>>(entry: S) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(LOG_PATH)?;
+    writeln!(
+        file,
+        "{} {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        entry.as_ref()
+    )
+}
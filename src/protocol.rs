@@ -15,6 +15,19 @@ You should have received a copy of the GNU Affero General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>. */
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The lowest auth level that is allowed to moderate the chat.
+pub const AUTH_MODERATOR: usize = 1;
+
+/// Names the service someone signed in with, going by the `auth` field of
+/// their messages.
+pub fn auth_provider(auth: usize) -> &'static str {
+    match auth {
+        0 => "Google",
+        _ => "an unknown provider",
+    }
+}
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(content = "data", rename_all = "lowercase", tag = "type")]
@@ -70,18 +83,34 @@ impl InboundMessage {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase", tag = "auth")]
 pub enum MessageAuth {
     Google { token: String },
 }
 
+// The token is as good as a password, so keep it out of any debug output.
+impl fmt::Debug for MessageAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageAuth::Google { .. } => f
+                .debug_struct("Google")
+                .field("token", &"<redacted>")
+                .finish(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(content = "data", rename_all = "lowercase", tag = "type")]
 enum OutboundData {
+    Ban { user: usize },
+    Delete { messages: Vec<usize> },
     Hello { last_message: isize },
     GetUserConf,
     Message { reply: usize, text: String },
+    SetUserConf { color: String, name: String },
+    Timeout { user: usize, duration: u64 },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -93,6 +122,20 @@ pub struct OutboundMessage {
 }
 
 impl OutboundMessage {
+    pub fn ban(auth: &MessageAuth, user: usize) -> Self {
+        Self {
+            auth: auth.clone(),
+            data: OutboundData::Ban { user },
+        }
+    }
+
+    pub fn delete(auth: &MessageAuth, messages: Vec<usize>) -> Self {
+        Self {
+            auth: auth.clone(),
+            data: OutboundData::Delete { messages },
+        }
+    }
+
     pub fn hello(auth: &MessageAuth) -> Self {
         Self {
             auth: auth.clone(),
@@ -111,11 +154,27 @@ impl OutboundMessage {
         Self {
             auth: auth.clone(),
             data: OutboundData::Message {
-                reply: if let Some(id) = reply { id } else { 0 },
+                reply: reply.unwrap_or_default(),
                 text: content.into(),
             },
         }
     }
+
+    /// Changes our name and the color it's shown in.
+    pub fn set_user_conf(auth: &MessageAuth, name: String, color: String) -> Self {
+        Self {
+            auth: auth.clone(),
+            data: OutboundData::SetUserConf { color, name },
+        }
+    }
+
+    /// Times `user` out for `duration` seconds.
+    pub fn timeout(auth: &MessageAuth, user: usize, duration: u64) -> Self {
+        Self {
+            auth: auth.clone(),
+            data: OutboundData::Timeout { user, duration },
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
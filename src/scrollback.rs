@@ -0,0 +1,218 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! The message log. Only the rows in view get turned into widgets, so the log
+//! has to know how tall every row is without laying it out. Heights are
+//! measured in lines when a row is added and cached from then on.
+
+use std::{collections::VecDeque, ops::Range};
+
+pub struct Scrollback<T> {
+    entries: VecDeque<T>,
+    heights: VecDeque<u32>,
+    /// The number of lines above each entry, counted from the start of the
+    /// session. Subtract the first one to get a position in the log.
+    tops: VecDeque<u64>,
+    limit: usize,
+}
+
+impl<T> Scrollback<T> {
+    /// Creates a log that holds at most `limit` entries. Zero means there's no
+    /// limit.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            heights: VecDeque::new(),
+            tops: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Changes the entry at `index` in place and measures it again. Only
+    /// entries after it have to move, so changing the newest one is cheap.
+    pub fn amend<F: FnOnce(&mut T), H: Fn(&T) -> u32>(
+        &mut self,
+        index: usize,
+        change: F,
+        height: H,
+    ) {
+        let Some(entry) = self.entries.get_mut(index) else {
+            return;
+        };
+        change(entry);
+        self.heights[index] = height(entry);
+        if index + 1 < self.entries.len() {
+            self.stack();
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.entries.get(index)
+    }
+
+    /// Returns the height of the whole log in lines.
+    pub fn height(&self) -> u64 {
+        match (self.tops.front(), self.tops.back(), self.heights.back()) {
+            (Some(first), Some(last), Some(height)) => last - first + *height as u64,
+            _ => 0,
+        }
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut T> + ExactSizeIterator {
+        self.entries.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Adds an entry `height` lines tall to the end of the log. If that puts
+    /// the log over its limit, the oldest entry is dropped and returned.
+    pub fn push(&mut self, entry: T, height: u32) -> Option<T> {
+        let top = match (self.tops.back(), self.heights.back()) {
+            (Some(top), Some(height)) => top + *height as u64,
+            _ => 0,
+        };
+        self.entries.push_back(entry);
+        self.heights.push_back(height);
+        self.tops.push_back(top);
+        if self.limit != 0 && self.entries.len() > self.limit {
+            self.heights.pop_front();
+            self.tops.pop_front();
+            self.entries.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// Adds older entries to the start of the log. Only the newest of them
+    /// that still fit under the limit are kept, so that the entries already
+    /// in the log stay where they are. Returns how many were added.
+    pub fn prepend(&mut self, entries: Vec<(T, u32)>) -> usize {
+        let room = self.room().unwrap_or(usize::MAX);
+        let mut added = 0;
+        for (entry, height) in entries.into_iter().rev().take(room) {
+            self.entries.push_front(entry);
+            self.heights.push_front(height);
+            added += 1;
+        }
+        self.stack();
+        added
+    }
+
+    /// Returns how many more entries fit under the limit, or `None` if there
+    /// isn't one.
+    pub fn room(&self) -> Option<usize> {
+        (self.limit != 0).then(|| self.limit.saturating_sub(self.entries.len()))
+    }
+
+    /// Measures every entry again. Call this after changing entries in place
+    /// or when whatever `height` depends on changes.
+    pub fn refresh<F: Fn(&T) -> u32>(&mut self, height: F) {
+        self.heights = self.entries.iter().map(height).collect();
+        self.stack();
+    }
+
+    /// Runs `change` over every entry and measures again only the ones it
+    /// says it changed.
+    pub fn update<F: FnMut(&mut T) -> bool, H: Fn(&T) -> u32>(&mut self, mut change: F, height: H) {
+        let mut moved = false;
+        for (entry, old) in self.entries.iter_mut().zip(self.heights.iter_mut()) {
+            if change(entry) {
+                let new = height(entry);
+                moved |= new != *old;
+                *old = new;
+            }
+        }
+        if moved {
+            self.stack();
+        }
+    }
+
+    /// Works out where every entry starts from the heights.
+    fn stack(&mut self) {
+        let mut top = 0;
+        self.tops = self
+            .heights
+            .iter()
+            .map(|height| {
+                let current = top;
+                top += *height as u64;
+                current
+            })
+            .collect();
+    }
+
+    /// Empties the log and hands back everything in it, oldest first, so it
+    /// can be rebuilt with `prepend`.
+    pub fn take(&mut self) -> Vec<T> {
+        self.heights.clear();
+        self.tops.clear();
+        self.entries.drain(..).collect()
+    }
+
+    /// Drops the entries `keep` turns down. The rest keep the heights they
+    /// were measured at.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, keep: F) {
+        let kept: Vec<bool> = self.entries.iter().map(keep).collect();
+        let mut flags = kept.iter();
+        self.entries.retain(|_| *flags.next().unwrap());
+        let mut flags = kept.iter();
+        self.heights.retain(|_| *flags.next().unwrap());
+        self.stack();
+    }
+
+    /// Changes how many entries the log holds, dropping the oldest ones if it
+    /// shrank. Returns how many were dropped.
+    pub fn set_limit(&mut self, limit: usize) -> usize {
+        self.limit = limit;
+        let excess = if limit == 0 {
+            0
+        } else {
+            self.entries.len().saturating_sub(limit)
+        };
+        self.entries.drain(..excess);
+        self.heights.drain(..excess);
+        self.tops.drain(..excess);
+        excess
+    }
+
+    /// Returns the number of lines above the entry at `index`.
+    pub fn top(&self, index: usize) -> u64 {
+        match (self.tops.front(), self.tops.get(index)) {
+            (Some(first), Some(top)) => top - first,
+            _ => self.height(),
+        }
+    }
+
+    /// Finds the entries that overlap the `lines` lines starting at `first`.
+    pub fn visible(&self, first: f32, lines: f32) -> Range<usize> {
+        let base = self.tops.front().copied().unwrap_or_default();
+        let first = first.max(0.0) as u64 + base;
+        let last = first + lines.max(0.0).ceil() as u64;
+        // The entry containing `first` is the last one starting at or before it.
+        let start = self
+            .tops
+            .partition_point(|top| *top <= first)
+            .saturating_sub(1);
+        let end = self.tops.partition_point(|top| *top < last);
+        start..end.max(start)
+    }
+}
@@ -0,0 +1,265 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Finding things in the log. The bar searches whatever is loaded, and older
+//! history on disk can be searched through its index on request. Both only
+//! match the starts of words, unless the query is a regex.
+
+use crate::{history::Record, markup, protocol::InboundData, theme::Palette};
+use chrono::{Local, TimeZone};
+use iced::{
+    widget::{button, checkbox, column, row, scrollable, text, text_input, Column},
+    Element, Length,
+};
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
+
+pub static INPUT: Lazy<text_input::Id> = Lazy::new(text_input::Id::unique);
+
+/// What to look for. Plain text finds messages with a word starting with
+/// each word of the query, which is how the history index looks words up, so
+/// the log and history find the same things. Regexes match anywhere.
+#[derive(Clone)]
+pub struct Query {
+    case_sensitive: bool,
+    pattern: Regex,
+    /// The words of a plain query, lowercase unless the case has to match.
+    prefixes: Vec<String>,
+    regex: bool,
+    text: String,
+}
+
+impl Query {
+    pub fn new(text: &str, regex: bool, case_sensitive: bool) -> Result<Self, regex::Error> {
+        let pattern = if regex {
+            text.to_string()
+        } else {
+            regex::escape(text)
+        };
+        let prefixes = if regex {
+            Vec::new()
+        } else {
+            split(text).map(|word| fold(word, case_sensitive)).collect()
+        };
+        Ok(Self {
+            case_sensitive,
+            pattern: RegexBuilder::new(&pattern)
+                .case_insensitive(!case_sensitive)
+                .build()?,
+            prefixes,
+            regex,
+            text: text.to_string(),
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        // A query that's all punctuation has no words to go by.
+        if self.regex || self.prefixes.is_empty() {
+            return self.pattern.is_match(text);
+        }
+        let words: Vec<String> = split(text)
+            .map(|word| fold(word, self.case_sensitive))
+            .collect();
+        self.prefixes
+            .iter()
+            .all(|prefix| words.iter().any(|word| word.starts_with(prefix.as_str())))
+    }
+
+    /// Returns the words a match has to contain, for looking them up in an
+    /// index. Regexes can match anything, so they can't use one.
+    pub fn words(&self) -> Option<Vec<String>> {
+        if self.regex {
+            return None;
+        }
+        let words = words(&self.text);
+        if words.is_empty() {
+            None
+        } else {
+            Some(words)
+        }
+    }
+}
+
+/// Splits `text` into lowercase words the way the history index does.
+pub fn words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = split(text).map(str::to_lowercase).collect();
+    words.sort();
+    words.dedup();
+    words
+}
+
+fn split(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+fn fold(word: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        word.to_string()
+    } else {
+        word.to_lowercase()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    CaseChange(bool),
+    Close,
+    /// What searching history for the query in the text found.
+    HistoryFound(String, Result<Vec<Record>, String>),
+    Next,
+    Previous,
+    QueryChange(String),
+    RegexChange(bool),
+    SearchHistory,
+}
+
+/// The state of the search bar. `matches` holds positions in the log, oldest
+/// first.
+pub struct Search {
+    pub case_sensitive: bool,
+    /// Which of `matches` is selected.
+    pub current: Option<usize>,
+    pub error: Option<String>,
+    /// Matches from history that isn't loaded, oldest first.
+    pub history: Vec<Record>,
+    pub matches: Vec<usize>,
+    /// The query built from the bar, if it has one.
+    pub query: Option<Query>,
+    pub regex: bool,
+    pub text: String,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self {
+            case_sensitive: false,
+            current: None,
+            error: None,
+            history: Vec::new(),
+            matches: Vec::new(),
+            query: None,
+            regex: false,
+            text: String::new(),
+        }
+    }
+
+    /// Builds the query again after the text or a toggle changed. A broken
+    /// regex is reported in the bar.
+    pub fn compile(&mut self) {
+        self.error = None;
+        self.history.clear();
+        self.query = None;
+        if self.text.is_empty() {
+            return;
+        }
+        match Query::new(&self.text, self.regex, self.case_sensitive) {
+            Ok(query) => self.query = Some(query),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Selects the next match, or the previous one if `forward` is false,
+    /// and returns its position in the log. It wraps around at both ends.
+    pub fn step(&mut self, forward: bool) -> Option<usize> {
+        if self.matches.is_empty() {
+            return None;
+        }
+        let last = self.matches.len() - 1;
+        let current = match (self.current, forward) {
+            (Some(i), true) if i < last => i + 1,
+            (Some(_), true) => 0,
+            (Some(i), false) if i > 0 => i - 1,
+            // Searching usually starts from the bottom of the log.
+            _ => last,
+        };
+        self.current = Some(current);
+        self.matches.get(current).copied()
+    }
+
+    /// Returns whether the entry at `i` in the log is a match, and whether
+    /// it's the selected one.
+    pub fn highlight(&self, i: usize) -> Option<bool> {
+        let found = self.matches.binary_search(&i).ok()?;
+        Some(self.current == Some(found))
+    }
+
+    pub fn view(&self, text_size: u16, palette: &Palette, timestamp: &str) -> Element<'_, Event> {
+        let count = match (self.current, self.matches.len()) {
+            (_, 0) if !self.text.is_empty() => String::from("No matches"),
+            (_, 0) => String::new(),
+            (Some(i), total) => format!("{}/{total}", i + 1),
+            (None, total) => format!("{total} matches"),
+        };
+        let mut bar = column![row![
+            text_input("Search the starts of words", &self.text, Event::QueryChange)
+                .id(INPUT.clone())
+                .on_submit(Event::Next)
+                .size(text_size),
+            checkbox("Regex", self.regex, Event::RegexChange).text_size(text_size),
+            checkbox("Match case", self.case_sensitive, Event::CaseChange).text_size(text_size),
+            text(count).size(text_size),
+            button(text("Previous").size(text_size)).on_press(Event::Previous),
+            button(text("Next").size(text_size)).on_press(Event::Next),
+            button(text("Search history").size(text_size)).on_press(Event::SearchHistory),
+            button(text("Close").size(text_size)).on_press(Event::Close),
+        ]
+        .spacing(4)
+        .align_items(iced::Alignment::Center)]
+        .spacing(4);
+        if let Some(error) = &self.error {
+            bar = bar.push(text(error).style(palette.part).size(text_size));
+        }
+        if !self.history.is_empty() {
+            let results = Column::with_children(
+                self.history
+                    .iter()
+                    .rev()
+                    .filter_map(|record| {
+                        let InboundData::Chat {
+                            author,
+                            message,
+                            time,
+                            ..
+                        } = record.message.data()
+                        else {
+                            return None;
+                        };
+                        let time = Local.timestamp_millis_opt(*time as _).single()?;
+                        Some(Element::from(
+                            text(format!(
+                                "{}{author}: {}",
+                                time.format(timestamp),
+                                markup::plain(&markup::parse(message))
+                            ))
+                            .size(text_size),
+                        ))
+                    })
+                    .collect(),
+            );
+            bar = bar.push(
+                column![
+                    text(format!("{} older matches in history", self.history.len()))
+                        .style(palette.system)
+                        .size(text_size),
+                    scrollable(results).height(Length::Units(text_size * 10)),
+                ]
+                .spacing(4),
+            );
+        }
+        bar.into()
+    }
+}
@@ -0,0 +1,312 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+use crate::{
+    config::{self, Configuration, DeletedMode, Ignored, IgnoredMode, Layout, PresenceMode},
+    theme::{self, Palette},
+    token::{self, Secret},
+};
+use chrono::Local;
+use iced::{
+    widget::{button, column, pick_list, row, scrollable, text, text_input, Column},
+    Element, Length,
+};
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    AddScript,
+    Cancel,
+    DeletedChange(DeletedMode),
+    IgnoredModeChange(IgnoredMode),
+    LayoutChange(Layout),
+    NewScriptChange(String),
+    PassphraseChange(Secret),
+    PresenceChange(PresenceMode),
+    RemoveScript(usize),
+    Save,
+    ServerChange(String),
+    TextSizeChange(String),
+    ThemeChange(String),
+    TimestampChange(String),
+    TokenChange(Secret),
+    Unignore(usize),
+}
+
+const TOKEN_STORE_PATH: &str = "token.store";
+
+/// The state of the settings screen. Everything is kept as the raw text the
+/// user typed until they hit save.
+pub struct Settings {
+    deleted: DeletedMode,
+    error: Option<String>,
+    external_token: Option<String>,
+    ignored: Vec<Ignored>,
+    ignored_mode: IgnoredMode,
+    layout: Layout,
+    new_script: String,
+    passphrase: String,
+    presence: PresenceMode,
+    scripts: Vec<String>,
+    server: String,
+    text_size: String,
+    theme: String,
+    themes: Vec<String>,
+    timestamp: String,
+    token: String,
+}
+
+impl Settings {
+    pub fn new(config: &Configuration) -> Self {
+        Self {
+            deleted: config.deleted,
+            error: None,
+            external_token: config
+                .token_command()
+                .map(|_| String::from("token_command"))
+                .or_else(|| config.token_file().cloned()),
+            ignored: config.ignored.clone(),
+            ignored_mode: config.ignored_mode,
+            layout: config.layout,
+            new_script: String::new(),
+            passphrase: String::new(),
+            presence: config.presence,
+            scripts: config.scripts().clone(),
+            server: config.server().clone(),
+            text_size: config.text_size.to_string(),
+            theme: config.theme.clone(),
+            themes: theme::available(),
+            timestamp: config.timestamp.clone(),
+            token: config.token().clone(),
+        }
+    }
+
+    /// Builds a new configuration out of `base` with the edited settings
+    /// applied on top. A newly encrypted token comes back staged, to be
+    /// committed once the configuration has been saved.
+    pub fn apply(
+        &self,
+        base: &Configuration,
+    ) -> Result<(Configuration, Option<token::Staged>), String> {
+        let text_size = self
+            .text_size
+            .trim()
+            .parse()
+            .map_err(|_| format!("\"{}\" is not a valid text size", self.text_size))?;
+        if !config::valid_timestamp(&self.timestamp) {
+            return Err(format!(
+                "\"{}\" is not a valid timestamp format",
+                self.timestamp
+            ));
+        }
+        let mut config = base.clone();
+        let mut staged = None;
+        config.set_scripts(self.scripts.clone());
+        config.set_server(self.server.trim().to_string());
+        if self.external_token.is_none() {
+            if !self.passphrase.is_empty() {
+                if self.token.is_empty() {
+                    return Err(String::from("Enter the token to encrypt"));
+                }
+                let path = base
+                    .token_store()
+                    .cloned()
+                    .unwrap_or_else(|| String::from(TOKEN_STORE_PATH));
+                staged = Some(
+                    token::stage(&path, &self.token, &self.passphrase)
+                        .map_err(|e| format!("Failed to encrypt the token: {e}"))?,
+                );
+                config.set_token(String::new());
+                config.set_token_store(Some(path));
+            } else if !self.token.is_empty() {
+                config.set_token(self.token.clone());
+                config.set_token_store(None);
+            }
+        }
+        config.deleted = self.deleted;
+        config.ignored = self.ignored.clone();
+        config.ignored_mode = self.ignored_mode;
+        config.layout = self.layout;
+        config.presence = self.presence;
+        config.text_size = text_size;
+        config.theme = self.theme.clone();
+        config.timestamp = self.timestamp.clone();
+        Ok((config, staged))
+    }
+
+    /// Returns the passphrase the token was just encrypted with, if any.
+    pub fn passphrase(&self) -> Option<&str> {
+        if self.passphrase.is_empty() {
+            None
+        } else {
+            Some(&self.passphrase)
+        }
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn update(&mut self, event: Event) {
+        match event {
+            Event::AddScript => {
+                let path = self.new_script.trim();
+                if !path.is_empty() {
+                    self.scripts.push(path.to_string());
+                }
+                self.new_script.clear();
+            }
+            Event::DeletedChange(mode) => self.deleted = mode,
+            Event::IgnoredModeChange(mode) => self.ignored_mode = mode,
+            Event::LayoutChange(layout) => self.layout = layout,
+            Event::NewScriptChange(s) => self.new_script = s,
+            Event::PassphraseChange(Secret(s)) => self.passphrase = s,
+            Event::PresenceChange(mode) => self.presence = mode,
+            Event::RemoveScript(i) => {
+                if i < self.scripts.len() {
+                    self.scripts.remove(i);
+                }
+            }
+            Event::ServerChange(s) => self.server = s,
+            Event::TextSizeChange(s) => self.text_size = s,
+            Event::ThemeChange(s) => self.theme = s,
+            Event::TimestampChange(s) => self.timestamp = s,
+            Event::TokenChange(Secret(s)) => self.token = s,
+            Event::Unignore(i) => {
+                if i < self.ignored.len() {
+                    self.ignored.remove(i);
+                }
+            }
+            // Saving and cancelling need the rest of the client, so they're
+            // handled by the caller.
+            Event::Cancel | Event::Save => {}
+        }
+    }
+
+    pub fn view(&self, text_size: u16, palette: &Palette) -> Element<'_, Event> {
+        let preview = if config::valid_timestamp(&self.timestamp) {
+            text(format!("Preview: {}", Local::now().format(&self.timestamp)))
+        } else {
+            text("Invalid timestamp format").style(palette.part)
+        };
+        let scripts = Column::with_children(
+            self.scripts
+                .iter()
+                .enumerate()
+                .map(|(i, path)| {
+                    Element::from(row![
+                        text(path).size(text_size).width(Length::Fill),
+                        button(text("Remove").size(text_size)).on_press(Event::RemoveScript(i))
+                    ])
+                })
+                .collect(),
+        )
+        .spacing(4);
+        let ignored = Column::with_children(
+            self.ignored
+                .iter()
+                .enumerate()
+                .map(|(i, user)| {
+                    Element::from(row![
+                        text(user).size(text_size).width(Length::Fill),
+                        button(text("Unignore").size(text_size)).on_press(Event::Unignore(i))
+                    ])
+                })
+                .collect(),
+        )
+        .spacing(4);
+        let token = if let Some(source) = &self.external_token {
+            column![text(format!("The token is read from {source}")).size(text_size)]
+        } else {
+            column![
+                text_input("Token", &self.token, |s| Event::TokenChange(Secret(s)))
+                    .password()
+                    .size(text_size),
+                text_input(
+                    "Passphrase to encrypt the token with (optional)",
+                    &self.passphrase,
+                    |s| Event::PassphraseChange(Secret(s))
+                )
+                .password()
+                .size(text_size),
+            ]
+            .spacing(8)
+        };
+        let mut content = column![
+            text("Server").size(text_size),
+            text_input("server.mattkc.com", &self.server, Event::ServerChange).size(text_size),
+            text("Token").size(text_size),
+            token,
+            text("Text size").size(text_size),
+            text_input("16", &self.text_size, Event::TextSizeChange).size(text_size),
+            text("Theme").size(text_size),
+            pick_list(
+                &self.themes[..],
+                Some(self.theme.clone()),
+                Event::ThemeChange
+            )
+            .text_size(text_size),
+            text("Timestamp format").size(text_size),
+            text_input("%r ", &self.timestamp, Event::TimestampChange).size(text_size),
+            preview.size(text_size),
+            text("Layout").size(text_size),
+            pick_list(&Layout::ALL[..], Some(self.layout), Event::LayoutChange)
+                .text_size(text_size),
+            text("Deleted messages").size(text_size),
+            pick_list(
+                &DeletedMode::ALL[..],
+                Some(self.deleted),
+                Event::DeletedChange
+            )
+            .text_size(text_size),
+            text("Messages from ignored users").size(text_size),
+            pick_list(
+                &IgnoredMode::ALL[..],
+                Some(self.ignored_mode),
+                Event::IgnoredModeChange
+            )
+            .text_size(text_size),
+            ignored,
+            text("Joins and parts").size(text_size),
+            pick_list(
+                &PresenceMode::ALL[..],
+                Some(self.presence),
+                Event::PresenceChange
+            )
+            .text_size(text_size),
+            text("Scripts").size(text_size),
+            scripts,
+            row![
+                text_input("Path to script", &self.new_script, Event::NewScriptChange)
+                    .on_submit(Event::AddScript)
+                    .size(text_size),
+                button(text("Add").size(text_size)).on_press(Event::AddScript)
+            ],
+        ]
+        .spacing(8)
+        .padding(16);
+        if let Some(error) = &self.error {
+            content = content.push(text(error).style(palette.part).size(text_size));
+        }
+        content = content.push(
+            row![
+                button(text("Save").size(text_size)).on_press(Event::Save),
+                button(text("Cancel").size(text_size)).on_press(Event::Cancel)
+            ]
+            .spacing(8),
+        );
+        scrollable(content).height(Length::Fill).into()
+    }
+}
@@ -23,8 +23,10 @@ use iced::subscription::{self, Subscription};
 pub struct Connection(mpsc::Sender<OutboundMessage>);
 
 impl Connection {
-    pub fn send(&mut self, payload: OutboundMessage) {
-        self.0.try_send(payload).unwrap();
+    /// Queues a message for the server, handing back the error if the queue is
+    /// full or the connection is already gone.
+    pub fn try_send(&mut self, payload: OutboundMessage) -> Result<(), String> {
+        self.0.try_send(payload).map_err(|e| e.to_string())
     }
 }
 
@@ -39,17 +41,25 @@ enum State {
     Connected(
         MessageAuth,
         String,
-        async_tungstenite::WebSocketStream<async_tungstenite::tokio::ConnectStream>,
+        Box<async_tungstenite::WebSocketStream<async_tungstenite::tokio::ConnectStream>>,
         mpsc::Receiver<OutboundMessage>,
     ),
     Disconnected(MessageAuth, String),
 }
 
-pub fn connect(auth: MessageAuth, server: String) -> Subscription<Event> {
+/// Connects to `server` using `auth`. The subscription is keyed on both, so
+/// changing either one drops the old connection and opens a new one. Bumping
+/// `generation` does the same without changing anything else.
+pub fn connect(auth: MessageAuth, server: String, generation: usize) -> Subscription<Event> {
     struct Connect;
 
     subscription::unfold(
-        std::any::TypeId::of::<Connect>(),
+        (
+            std::any::TypeId::of::<Connect>(),
+            auth.clone(),
+            server.clone(),
+            generation,
+        ),
         State::Disconnected(auth, server),
         |state| async move {
             match state {
@@ -96,7 +106,7 @@ pub fn connect(auth: MessageAuth, server: String) -> Subscription<Event> {
 
                             (
                                 Some(Event::Connected(Connection(sender))),
-                                State::Connected(auth, server, websock, receiver),
+                                State::Connected(auth, server, Box::new(websock), receiver),
                             )
                         }
                         Err(_) => {
@@ -0,0 +1,309 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+use iced::{theme, widget::container, Color, Theme};
+use serde::{de, Deserialize, Deserializer};
+use std::{collections::BTreeMap, fmt, fs, io, path::Path};
+
+/// The themes compiled into the client.
+const BUILTIN: &[(&str, &str)] = &[
+    ("dark", include_str!("../themes/dark.toml")),
+    (
+        "high-contrast",
+        include_str!("../themes/high-contrast.toml"),
+    ),
+    ("light", include_str!("../themes/light.toml")),
+];
+
+/// The contrast ratio author colors are pushed to, which is the WCAG AA
+/// minimum for normal text.
+const MIN_CONTRAST: f32 = 4.5;
+
+/// Where user themes are looked up by name.
+const THEME_DIR: &str = "themes";
+
+/// A marker drawn in front of the names of people with a given level.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Badge {
+    #[serde(deserialize_with = "hex")]
+    pub color: Color,
+    pub glyph: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Palette {
+    #[serde(deserialize_with = "hex")]
+    pub background: Color,
+    /// Badges keyed on author level. TOML keys are always strings, so the
+    /// levels are too.
+    #[serde(default)]
+    pub badges: BTreeMap<String, Badge>,
+    #[serde(deserialize_with = "hex")]
+    pub highlight: Color,
+    #[serde(deserialize_with = "hex")]
+    pub join: Color,
+    #[serde(deserialize_with = "hex")]
+    pub mention: Color,
+    #[serde(deserialize_with = "hex")]
+    pub part: Color,
+    #[serde(deserialize_with = "hex")]
+    pub system: Color,
+    #[serde(deserialize_with = "hex")]
+    pub text: Color,
+    #[serde(deserialize_with = "hex")]
+    pub timestamp: Color,
+}
+
+impl Palette {
+    /// Loads a theme by name. Built-in themes come first, then
+    /// `themes/<name>.toml`, and finally `name` is tried as a path.
+    pub fn load(name: &str) -> Result<Self, ThemeError> {
+        if let Some((_, buffer)) = BUILTIN.iter().find(|(builtin, _)| *builtin == name) {
+            return toml::from_str(buffer).map_err(ThemeError::Parse);
+        }
+        let named = Path::new(THEME_DIR).join(format!("{name}.toml"));
+        let path = if named.exists() {
+            named
+        } else {
+            Path::new(name).to_path_buf()
+        };
+        let buffer = fs::read_to_string(path).map_err(ThemeError::Io)?;
+        toml::from_str(&buffer).map_err(ThemeError::Parse)
+    }
+
+    pub fn badge(&self, level: usize) -> Option<&Badge> {
+        self.badges.get(&level.to_string())
+    }
+
+    pub fn theme(&self) -> Theme {
+        Theme::custom(theme::Palette {
+            background: self.background,
+            text: self.text,
+            primary: self.highlight,
+            success: self.join,
+            danger: self.part,
+        })
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::load("dark").unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum ThemeError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThemeError::Io(e) => write!(f, "{e}"),
+            ThemeError::Parse(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Tints the background of donation messages with the highlight color.
+pub fn donation() -> theme::Container {
+    theme::Container::from(donation_appearance as fn(&Theme) -> container::Appearance)
+}
+
+fn donation_appearance(theme: &Theme) -> container::Appearance {
+    container::Appearance {
+        background: Some(
+            Color {
+                a: 0.25,
+                ..theme.palette().primary
+            }
+            .into(),
+        ),
+        border_radius: 4.0,
+        ..Default::default()
+    }
+}
+
+/// Tints the background with `color`, like messages that mention us.
+pub fn tint(color: Color) -> theme::Container {
+    theme::Container::Custom(Box::new(Tint(color)))
+}
+
+struct Tint(Color);
+
+impl container::StyleSheet for Tint {
+    type Style = Theme;
+
+    fn appearance(&self, _style: &Self::Style) -> container::Appearance {
+        container::Appearance {
+            background: Some(Color { a: 0.3, ..self.0 }.into()),
+            border_radius: 4.0,
+            ..Default::default()
+        }
+    }
+}
+
+/// Tints the background of search matches, more strongly for the selected
+/// one.
+pub fn search_match(current: bool) -> theme::Container {
+    if current {
+        theme::Container::from(current_match_appearance as fn(&Theme) -> container::Appearance)
+    } else {
+        theme::Container::from(match_appearance as fn(&Theme) -> container::Appearance)
+    }
+}
+
+fn match_appearance(theme: &Theme) -> container::Appearance {
+    container::Appearance {
+        background: Some(
+            Color {
+                a: 0.2,
+                ..theme.palette().success
+            }
+            .into(),
+        ),
+        ..Default::default()
+    }
+}
+
+fn current_match_appearance(theme: &Theme) -> container::Appearance {
+    container::Appearance {
+        background: Some(
+            Color {
+                a: 0.5,
+                ..theme.palette().success
+            }
+            .into(),
+        ),
+        ..Default::default()
+    }
+}
+
+/// Lists the names of the built-in themes followed by any found in `themes/`.
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = BUILTIN.iter().map(|(name, _)| name.to_string()).collect();
+    if let Ok(entries) = fs::read_dir(THEME_DIR) {
+        let mut found: Vec<String> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .filter(|name| !names.contains(name))
+            .collect();
+        found.sort();
+        names.extend(found);
+    }
+    names
+}
+
+/// Returns the WCAG contrast ratio between two colors, from 1 to 21.
+pub fn contrast(a: Color, b: Color) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Adjusts the lightness of `color` until it is readable on `background`.
+/// Hue and saturation are left alone so that people keep looking like
+/// themselves.
+pub fn readable(color: Color, background: Color) -> Color {
+    if contrast(color, background) >= MIN_CONTRAST {
+        return color;
+    }
+    let (hue, saturation, mut lightness) = to_hsl(color);
+    let step = if luminance(background) < 0.5 {
+        0.02
+    } else {
+        -0.02
+    };
+    let mut adjusted = color;
+    while contrast(adjusted, background) < MIN_CONTRAST && (0.0..=1.0).contains(&lightness) {
+        lightness += step;
+        adjusted = from_hsl(hue, saturation, lightness.clamp(0.0, 1.0));
+    }
+    adjusted
+}
+
+fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Color {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+    let m = lightness - chroma / 2.0;
+    let (r, g, b) = match hue as u32 {
+        0..=59 => (chroma, x, 0.0),
+        60..=119 => (x, chroma, 0.0),
+        120..=179 => (0.0, chroma, x),
+        180..=239 => (0.0, x, chroma),
+        240..=299 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    Color::from_rgb(r + m, g + m, b + m)
+}
+
+fn luminance(color: Color) -> f32 {
+    let linear = |c: f32| {
+        if c <= 0.03928 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(color.r) + 0.7152 * linear(color.g) + 0.0722 * linear(color.b)
+}
+
+fn to_hsl(color: Color) -> (f32, f32, f32) {
+    let max = color.r.max(color.g).max(color.b);
+    let min = color.r.min(color.g).min(color.b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == color.r {
+        60.0 * (((color.g - color.b) / delta).rem_euclid(6.0))
+    } else if max == color.g {
+        60.0 * ((color.b - color.r) / delta + 2.0)
+    } else {
+        60.0 * ((color.r - color.g) / delta + 4.0)
+    };
+    (hue, saturation, lightness)
+}
+
+/// Parses a color written as `RRGGBB`, with or without a leading `#`.
+pub fn parse_color(raw: &str) -> Option<Color> {
+    let raw = raw.strip_prefix('#').unwrap_or(raw);
+    if raw.len() != 6 {
+        return None;
+    }
+    let raw = u32::from_str_radix(raw, 16).ok()?;
+    let red = ((raw & 0xFF0000) >> 16) as u8;
+    let green = ((raw & 0xFF00) >> 8) as u8;
+    let blue = (raw & 0xFF) as u8;
+    Some(Color::from_rgb8(red, green, blue))
+}
+
+/// Writes `color` as `#RRGGBB`.
+pub fn to_hex(color: Color) -> String {
+    let [red, green, blue, _] = color.into_rgba8();
+    format!("#{red:02x}{green:02x}{blue:02x}")
+}
+
+fn hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    parse_color(&raw).ok_or_else(|| de::Error::custom(format!("\"{raw}\" is not a color")))
+}
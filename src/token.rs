@@ -0,0 +1,214 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+use crate::{config::Configuration, protocol::MessageAuth};
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use std::{fmt, fs, io, path::Path, process::Command, sync::Arc};
+
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+#[derive(Debug)]
+pub enum TokenError {
+    Command(String),
+    Io(io::Error),
+    Locked,
+    Permissions(String),
+    Store,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Command(e) => write!(f, "token_command failed: {e}"),
+            TokenError::Io(e) => write!(f, "{e}"),
+            TokenError::Locked => write!(f, "the token store is locked"),
+            TokenError::Permissions(path) => write!(
+                f,
+                "{path} can be read by other users, restrict it with chmod 600"
+            ),
+            TokenError::Store => write!(f, "wrong passphrase or corrupted token store"),
+        }
+    }
+}
+
+/// A token or passphrase travelling through the UI's events, which all derive
+/// `Debug`. Its own `Debug` keeps the contents out of any output.
+#[derive(Clone)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Figures out the token to authenticate with. `token_command` wins over
+/// `token_file`, which wins over `token_store`, which wins over `token`. The
+/// passphrase is only needed to open the token store. Runs on a blocking
+/// thread, since the command and the key derivation can both take a while.
+pub async fn resolve(
+    config: Configuration,
+    passphrase: Option<String>,
+) -> Result<MessageAuth, Arc<TokenError>> {
+    match tokio::task::spawn_blocking(move || lookup(&config, passphrase.as_deref())).await {
+        Ok(result) => result
+            .map(|token| MessageAuth::Google { token })
+            .map_err(Arc::new),
+        Err(e) => Err(Arc::new(TokenError::Io(io::Error::other(e)))),
+    }
+}
+
+fn lookup(config: &Configuration, passphrase: Option<&str>) -> Result<String, TokenError> {
+    if let Some(command) = config.token_command() {
+        run_command(command)
+    } else if let Some(path) = config.token_file() {
+        read_file(path)
+    } else if let Some(path) = config.token_store() {
+        match passphrase {
+            Some(passphrase) => decrypt(path, passphrase),
+            None => Err(TokenError::Locked),
+        }
+    } else {
+        Ok(config.token().clone())
+    }
+}
+
+/// Encrypts `token` with a key derived from `passphrase` and writes it to
+/// `path`. The file is laid out as the salt, then the nonce, then the
+/// ciphertext.
+pub fn encrypt<P: AsRef<Path>>(path: P, token: &str, passphrase: &str) -> Result<(), TokenError> {
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = cipher(passphrase, &salt)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, token.as_bytes())
+        .map_err(|_| TokenError::Store)?;
+    let mut buffer = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    buffer.extend_from_slice(&salt);
+    buffer.extend_from_slice(&nonce);
+    buffer.extend_from_slice(&ciphertext);
+    write_private(path, &buffer).map_err(TokenError::Io)
+}
+
+/// A token store written next to where it belongs, so that the one in use is
+/// only replaced once the configuration pointing at it has been saved.
+#[derive(Debug)]
+pub struct Staged {
+    path: String,
+    staging: String,
+}
+
+impl Staged {
+    /// Moves the new token store into place.
+    pub fn commit(self) -> io::Result<()> {
+        fs::rename(&self.staging, &self.path)
+    }
+
+    /// Throws the new token store away, leaving the old one alone.
+    pub fn discard(self) {
+        let _ = fs::remove_file(&self.staging);
+    }
+}
+
+/// Like [`encrypt`], but writes to a staging file that has to be committed.
+pub fn stage(path: &str, token: &str, passphrase: &str) -> Result<Staged, TokenError> {
+    let staging = format!("{path}.new");
+    encrypt(&staging, token, passphrase)?;
+    Ok(Staged {
+        path: path.to_string(),
+        staging,
+    })
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, TokenError> {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| TokenError::Store)?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn decrypt(path: &str, passphrase: &str) -> Result<String, TokenError> {
+    let buffer = fs::read(path).map_err(TokenError::Io)?;
+    if buffer.len() < SALT_LEN + NONCE_LEN {
+        return Err(TokenError::Store);
+    }
+    let (salt, rest) = buffer.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let plaintext = cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| TokenError::Store)?;
+    String::from_utf8(plaintext).map_err(|_| TokenError::Store)
+}
+
+fn read_file(path: &str) -> Result<String, TokenError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let meta = fs::metadata(path).map_err(TokenError::Io)?;
+        if meta.permissions().mode() & 0o077 != 0 {
+            return Err(TokenError::Permissions(path.to_string()));
+        }
+    }
+    let token = fs::read_to_string(path).map_err(TokenError::Io)?;
+    Ok(token.trim().to_string())
+}
+
+fn run_command(command: &str) -> Result<String, TokenError> {
+    #[cfg(unix)]
+    let output = Command::new("sh").arg("-c").arg(command).output();
+    #[cfg(windows)]
+    let output = Command::new("cmd").arg("/C").arg(command).output();
+    let output = output.map_err(TokenError::Io)?;
+    if !output.status.success() {
+        return Err(TokenError::Command(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    String::from_utf8(output.stdout)
+        .map(|token| token.trim().to_string())
+        .map_err(|_| TokenError::Command(String::from("output is not valid UTF-8")))
+}
+
+fn write_private<P: AsRef<Path>>(path: P, buffer: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // The mode only applies when the file is created, so tighten up one
+    // that was already there too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(buffer)
+}
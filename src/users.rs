@@ -0,0 +1,88 @@
+/* An open source desktop client for ChatKC servers
+Copyright (C) 2023 Alexander Hill
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU Affero General Public License as published
+by the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU Affero General Public License for more details.
+
+You should have received a copy of the GNU Affero General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>. */
+
+//! Who is in the chat right now. ChatKC has no member list, so it's pieced
+//! together from joins, parts and whoever speaks.
+
+use iced::Color;
+use std::collections::BTreeMap;
+
+pub struct User {
+    pub color: Option<Color>,
+    /// Only known once they've said something.
+    pub id: Option<usize>,
+    pub level: usize,
+    pub name: String,
+}
+
+#[derive(Default)]
+pub struct Users {
+    by_name: BTreeMap<String, User>,
+}
+
+impl Users {
+    /// Forgets everyone, for when we lose the connection.
+    pub fn clear(&mut self) {
+        self.by_name.clear();
+    }
+
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.by_name.get(name)
+    }
+
+    pub fn join(&mut self, name: &str) {
+        self.by_name
+            .entry(name.to_string())
+            .or_insert_with(|| User {
+                color: None,
+                id: None,
+                level: 0,
+                name: name.to_string(),
+            });
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn part(&mut self, name: &str) {
+        self.by_name.remove(name);
+    }
+
+    /// Notes that `name` said something, which also means they're here.
+    pub fn spoke(&mut self, name: &str, id: usize, level: usize, color: Option<Color>) {
+        self.by_name.insert(
+            name.to_string(),
+            User {
+                color,
+                id: Some(id),
+                level,
+                name: name.to_string(),
+            },
+        );
+    }
+
+    /// Returns everyone, highest level first and then by name.
+    pub fn sorted(&self) -> Vec<&User> {
+        let mut users: Vec<&User> = self.by_name.values().collect();
+        users.sort_by(|a, b| {
+            b.level
+                .cmp(&a.level)
+                .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+        });
+        users
+    }
+}